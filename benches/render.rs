@@ -0,0 +1,58 @@
+// Measures how long it takes to render a single frame for files of different sizes.
+// The time per frame should stay roughly the same, no matter how many lines the file has.
+//
+// Run with: cargo bench --bench render
+use miv::{Content, MemoryBackend, Screen, Status, View};
+use regex::Regex;
+use std::time::Instant;
+
+const FRAMES: usize = 1000;
+const WIDTH: usize = 120;
+const HEIGHT: usize = 40;
+
+fn build_text(lines: usize) -> String {
+    (0..lines)
+        .map(|i| format!("{}: the quick brown fox jumps over the lazy dog\n", i))
+        .collect()
+}
+
+fn main() {
+    let highlight = Regex::new("fox").unwrap();
+
+    for lines in [1_000, 100_000, 1_000_000] {
+        let mut content = Content::from_text(&build_text(lines));
+        let mut screen = Screen::with_backend(MemoryBackend::new(WIDTH, HEIGHT)).unwrap();
+
+        let status = Status {
+            filename: "bench.txt",
+            modified: true,
+            file_type: "text",
+            mode: "INSERT",
+            message: None,
+        };
+
+        let row_offset = lines / 2;
+        let start = Instant::now();
+        for frame in 0..FRAMES {
+            // Edit the file between frames like typing would
+            content.insert_char(row_offset, 0, 'x');
+            let view = View {
+                cursor_row: row_offset + frame % (HEIGHT - 2),
+                cursor_column: 0,
+                row_offset,
+                column_offset: 0,
+                highlight: Some(&highlight),
+                current_match: None,
+                selection: None,
+            };
+            screen.render(&content, &view, &status);
+        }
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:>9} lines: {:>8.2?} per frame",
+            lines,
+            elapsed / FRAMES as u32
+        );
+    }
+}
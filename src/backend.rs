@@ -0,0 +1,264 @@
+use crate::constants::ALTERNATE_SCREEN;
+use crate::grapheme;
+use crate::input::{read_cursor_position, restore_keyboard_mode};
+use std::io::{self, Error, Write};
+use std::{env, mem};
+use unicode_segmentation::UnicodeSegmentation;
+
+// The escape sequence that sets the colors of a cell, None for the default colors
+pub type Style = Option<&'static str>;
+
+// Where the screen is drawn. Positions are cells starting at 0,
+// a wide character takes up the cell it is written to and the one after it.
+pub trait Backend {
+    // The width and height in cells
+    fn size(&mut self) -> Result<(usize, usize), Error>;
+
+    fn move_cursor(&mut self, x: usize, y: usize);
+
+    fn set_style(&mut self, style: Style);
+
+    // Writes the text at the cursor and moves the cursor after it
+    fn write(&mut self, text: &str);
+
+    // Clears the row from the cursor to its end
+    fn clear_row(&mut self);
+
+    fn clear(&mut self);
+
+    fn show_cursor(&mut self, visible: bool);
+
+    // Makes everything written so far visible
+    fn flush(&mut self) -> Result<(), Error>;
+}
+
+// Draws to the terminal with ANSI escape sequences
+pub struct AnsiBackend {
+    // Everything written since the last flush
+    buffer: Vec<u8>,
+
+    // Whether we switched to the alternate screen, which is left again on drop
+    alternate_screen: bool,
+}
+
+impl AnsiBackend {
+    pub fn new() -> AnsiBackend {
+        let mut backend = AnsiBackend {
+            buffer: vec![],
+            alternate_screen: *ALTERNATE_SCREEN,
+        };
+        // Switch first, so asking the terminal for its size does not move the cursor of the shell
+        if backend.alternate_screen {
+            backend.append("\x1b[?1049h");
+            let _ = backend.flush();
+        }
+        backend
+    }
+
+    fn append(&mut self, s: &str) {
+        self.buffer.extend(s.as_bytes());
+    }
+
+    // Fallback for terminals that do not support the ioctl:
+    // moves the cursor to the bottom right corner and asks the terminal where it ended up
+    fn size_from_cursor(&mut self) -> Option<(usize, usize)> {
+        self.append("\x1b[999C\x1b[999B\x1b[6n");
+        self.flush().ok()?;
+        let (row, column) = read_cursor_position()?;
+        Some((column, row))
+    }
+}
+
+impl Default for AnsiBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for AnsiBackend {
+    // LINES and COLUMNS override what the terminal reports
+    fn size(&mut self) -> Result<(usize, usize), Error> {
+        let (env_width, env_height) = (env_size("COLUMNS"), env_size("LINES"));
+        if let (Some(width), Some(height)) = (env_width, env_height) {
+            return Ok((width, height));
+        }
+
+        let (width, height) = term_size::dimensions_stdout()
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .or_else(|| self.size_from_cursor())
+            .ok_or_else(|| Error::other("could not determine the window size"))?;
+        Ok((env_width.unwrap_or(width), env_height.unwrap_or(height)))
+    }
+
+    fn move_cursor(&mut self, x: usize, y: usize) {
+        self.append(&format!("\x1b[{};{}H", y + 1, x + 1));
+    }
+
+    fn set_style(&mut self, style: Style) {
+        self.append("\x1b[m");
+        self.append(style.unwrap_or_default());
+    }
+
+    fn write(&mut self, text: &str) {
+        self.append(text);
+    }
+
+    fn clear_row(&mut self) {
+        self.append("\x1b[K");
+    }
+
+    fn clear(&mut self) {
+        self.append("\x1b[2J");
+    }
+
+    fn show_cursor(&mut self, visible: bool) {
+        match visible {
+            true => self.append("\x1b[?25h"),
+            false => self.append("\x1b[?25l"),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        let mut stdout = io::stdout();
+        let result = stdout.write_all(&self.buffer).and_then(|_| stdout.flush());
+        self.buffer.clear();
+        result
+    }
+}
+
+impl Drop for AnsiBackend {
+    fn drop(&mut self) {
+        self.buffer.clear();
+        // The keyboard mode was set on this screen, so it is restored before leaving it
+        restore_keyboard_mode();
+        // The cursor is hidden while it is scrolled out of view
+        self.show_cursor(true);
+        if self.alternate_screen {
+            // Returns to the shell as it was before
+            self.append("\x1b[?1049l");
+        } else {
+            self.append("\x1b[2J");
+            self.append("\x1b[H");
+        }
+        let _ = self.flush();
+    }
+}
+
+fn env_size(name: &str) -> Option<usize> {
+    env::var(name).ok()?.parse().ok().filter(|size| *size > 0)
+}
+
+// Records what is drawn in a grid of cells instead of drawing to a terminal, e.g. for tests
+pub struct MemoryBackend {
+    width: usize,
+    height: usize,
+    // The cells of all rows, the cells covered by a wide character are empty
+    cells: Vec<String>,
+    cursor: (usize, usize),
+    cursor_visible: bool,
+    // The position and text of every write since the last take_writes,
+    // clearing writes blanks to the cleared cells
+    writes: Vec<(usize, usize, String)>,
+}
+
+impl MemoryBackend {
+    pub fn new(width: usize, height: usize) -> MemoryBackend {
+        MemoryBackend {
+            width,
+            height,
+            cells: vec![" ".to_string(); width * height],
+            cursor: (0, 0),
+            cursor_visible: true,
+            writes: vec![],
+        }
+    }
+
+    // The text of every row
+    pub fn rows(&self) -> Vec<String> {
+        self.cells
+            .chunks(self.width.max(1))
+            .take(self.height)
+            .map(|row| row.concat())
+            .collect()
+    }
+
+    // The position of the cursor, None while it is hidden
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor_visible.then_some(self.cursor)
+    }
+
+    // What was drawn since the last call, to check that only changes are drawn
+    pub fn take_writes(&mut self) -> Vec<(usize, usize, String)> {
+        mem::take(&mut self.writes)
+    }
+
+    fn set_cell(&mut self, x: usize, y: usize, text: &str) {
+        let index = y * self.width + x;
+        // Overwriting half of a wide character removes all of it, like terminals do
+        if self.cells[index].is_empty() && x > 0 {
+            self.cells[index - 1] = " ".to_string();
+        }
+        if grapheme::width(&self.cells[index]) > 1 && x + 1 < self.width {
+            self.cells[index + 1] = " ".to_string();
+        }
+        self.cells[index] = text.to_string();
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn size(&mut self) -> Result<(usize, usize), Error> {
+        Ok((self.width, self.height))
+    }
+
+    fn move_cursor(&mut self, x: usize, y: usize) {
+        self.cursor = (x, y);
+    }
+
+    fn set_style(&mut self, _: Style) {}
+
+    fn write(&mut self, text: &str) {
+        let (mut x, y) = self.cursor;
+        if y >= self.height {
+            return;
+        }
+        self.writes.push((x, y, text.to_string()));
+        for grapheme in text.graphemes(true) {
+            let width = grapheme::width(grapheme);
+            if x + width > self.width {
+                break;
+            }
+            self.set_cell(x, y, grapheme);
+            for i in 1..width {
+                self.set_cell(x + i, y, "");
+            }
+            x += width;
+        }
+        self.cursor = (x, y);
+    }
+
+    fn clear_row(&mut self) {
+        let (x, y) = self.cursor;
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        self.writes.push((x, y, " ".repeat(self.width - x)));
+        for x in x..self.width {
+            self.set_cell(x, y, " ");
+        }
+    }
+
+    fn clear(&mut self) {
+        for y in 0..self.height {
+            self.writes.push((0, y, " ".repeat(self.width)));
+        }
+        self.cells.fill(" ".to_string());
+    }
+
+    fn show_cursor(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,515 @@
+// Ex-style commands entered on the ':' command line
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Write {
+        filename: Option<String>,
+        // Whether a read-only file is replaced, ':w!'
+        force: bool,
+        // Whether directories of the file that do not exist yet are created, ':w ++p'
+        create_directories: bool,
+    },
+    Quit {
+        force: bool,
+    },
+    // Like Write, ':wq!' replaces a read-only file
+    WriteQuit {
+        force: bool,
+    },
+    Edit {
+        filename: String,
+        force: bool,
+    },
+    Set {
+        option: String,
+        value: Option<String>,
+    },
+    GoToLine(Address),
+    NoHighlight,
+    // Nothing was entered, e.g. ':' followed by Enter
+    Empty,
+    Substitute {
+        range: Option<Range>,
+        pattern: String,
+        replacement: String,
+        flags: SubstituteFlags,
+    },
+}
+
+// A line of the content as written in a command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    // Lines are counted from 1
+    Line(usize),
+    // '.'
+    Current,
+    // '$'
+    Last,
+}
+
+// Lines a command applies to, e.g. '%' or '3,$'
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub start: Address,
+    pub end: Address,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SubstituteFlags {
+    // 'g': replace all matches in a line instead of only the first one
+    pub global: bool,
+    // 'c': confirm each replacement
+    pub confirm: bool,
+    // 'i' or 'I': ignore case or match case, overriding the options
+    pub ignore_case: Option<bool>,
+}
+
+// Builds the command from its range, whether it was forced with '!' and its argument
+type ParseFn =
+    fn(range: Option<Range>, force: bool, argument: Option<&str>) -> Result<Command, String>;
+
+struct CommandSpec {
+    // The full name of the command
+    name: &'static str,
+    // The shortest prefix of the name that is accepted, e.g. "w" for "write"
+    abbreviation: &'static str,
+    // Whether the command can be preceded by a range
+    accepts_range: bool,
+    parse: ParseFn,
+}
+
+// New commands are added here
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "write",
+        abbreviation: "w",
+        accepts_range: false,
+        parse: |_, force, argument| {
+            let (create_directories, filename) = match argument {
+                Some("++p") => (true, None),
+                Some(argument) => match argument.strip_prefix("++p ") {
+                    Some(filename) => (true, Some(filename.trim())),
+                    None => (false, Some(argument)),
+                },
+                None => (false, None),
+            };
+            Ok(Command::Write {
+                filename: filename.map(str::to_string),
+                force,
+                create_directories,
+            })
+        },
+    },
+    CommandSpec {
+        name: "quit",
+        abbreviation: "q",
+        accepts_range: false,
+        parse: |_, force, argument| {
+            no_argument("quit", argument)?;
+            Ok(Command::Quit { force })
+        },
+    },
+    CommandSpec {
+        name: "wq",
+        abbreviation: "wq",
+        accepts_range: false,
+        parse: |_, force, argument| {
+            no_argument("wq", argument)?;
+            Ok(Command::WriteQuit { force })
+        },
+    },
+    CommandSpec {
+        name: "edit",
+        abbreviation: "e",
+        accepts_range: false,
+        parse: |_, force, argument| match argument {
+            Some(filename) => Ok(Command::Edit {
+                filename: filename.to_string(),
+                force,
+            }),
+            None => Err("E32: No file name".to_string()),
+        },
+    },
+    CommandSpec {
+        name: "set",
+        abbreviation: "se",
+        accepts_range: false,
+        parse: |_, _, argument| {
+            let argument = argument.ok_or("E471: Argument required")?;
+            let (option, value) = match argument.split_once('=') {
+                Some((option, value)) => (option, Some(value.to_string())),
+                None => (argument, None),
+            };
+            Ok(Command::Set {
+                option: option.to_string(),
+                value,
+            })
+        },
+    },
+    CommandSpec {
+        name: "nohlsearch",
+        abbreviation: "noh",
+        accepts_range: false,
+        parse: |_, _, argument| {
+            no_argument("nohlsearch", argument)?;
+            Ok(Command::NoHighlight)
+        },
+    },
+    CommandSpec {
+        name: "substitute",
+        abbreviation: "s",
+        accepts_range: true,
+        parse: parse_substitute,
+    },
+];
+
+fn no_argument(name: &str, argument: Option<&str>) -> Result<(), String> {
+    match argument {
+        Some(_) => Err(format!("E488: Trailing characters: {}", name)),
+        None => Ok(()),
+    }
+}
+
+pub fn parse_command(input: &str) -> Result<Command, String> {
+    let input = input.trim();
+    let (range, rest) = parse_range(input)?;
+
+    // A range without a command jumps to its last line, e.g. ':12' or ':$'
+    if rest.is_empty() {
+        return match range {
+            Some(range) => Ok(Command::GoToLine(range.end)),
+            None => Ok(Command::Empty),
+        };
+    }
+
+    // Commands are a name, an optional '!' and an optional argument separated by whitespace
+    let name_end = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_end);
+    let (force, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let argument = Some(rest.trim()).filter(|argument| !argument.is_empty());
+
+    let spec = COMMANDS
+        .iter()
+        .find(|spec| name.starts_with(spec.abbreviation) && spec.name.starts_with(name))
+        .ok_or(format!("E492: Not an editor command: {}", input))?;
+
+    if range.is_some() && !spec.accepts_range {
+        return Err("E481: No range allowed".to_string());
+    }
+
+    (spec.parse)(range, force, argument)
+}
+
+fn parse_range(input: &str) -> Result<(Option<Range>, &str), String> {
+    if let Some(rest) = input.strip_prefix('%') {
+        let range = Range {
+            start: Address::Line(1),
+            end: Address::Last,
+        };
+        return Ok((Some(range), rest));
+    }
+
+    let (start, rest) = match parse_address(input)? {
+        (Some(start), rest) => (start, rest),
+        (None, rest) => return Ok((None, rest)),
+    };
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => match parse_address(rest)? {
+            (Some(end), rest) => (end, rest),
+            (None, _) => return Err("E14: Invalid address".to_string()),
+        },
+        None => (start, rest),
+    };
+
+    Ok((Some(Range { start, end }), rest))
+}
+
+fn parse_address(input: &str) -> Result<(Option<Address>, &str), String> {
+    if let Some(rest) = input.strip_prefix('.') {
+        return Ok((Some(Address::Current), rest));
+    }
+    if let Some(rest) = input.strip_prefix('$') {
+        return Ok((Some(Address::Last), rest));
+    }
+
+    let digits_end = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    if digits_end == 0 {
+        return Ok((None, input));
+    }
+
+    let line = input[..digits_end]
+        .parse()
+        .map_err(|_| "E14: Invalid address".to_string())?;
+    Ok((Some(Address::Line(line)), &input[digits_end..]))
+}
+
+// Parses the argument of ':s', e.g. '/pattern/replacement/flags'
+fn parse_substitute(
+    range: Option<Range>,
+    _: bool,
+    argument: Option<&str>,
+) -> Result<Command, String> {
+    let argument = argument.ok_or("E35: No previous regular expression")?;
+    let delimiter = argument.chars().next().unwrap_or('/');
+    if delimiter.is_alphanumeric() || delimiter == '\\' {
+        return Err("E146: Regular expressions can't be delimited by letters".to_string());
+    }
+
+    let (pattern, rest) = split_at_delimiter(&argument[delimiter.len_utf8()..], delimiter);
+    let (replacement, flags) = split_at_delimiter(rest.unwrap_or(""), delimiter);
+
+    let mut parsed_flags = SubstituteFlags {
+        global: false,
+        confirm: false,
+        ignore_case: None,
+    };
+    for flag in flags.unwrap_or("").chars() {
+        match flag {
+            'g' => parsed_flags.global = true,
+            'c' => parsed_flags.confirm = true,
+            'i' => parsed_flags.ignore_case = Some(true),
+            'I' => parsed_flags.ignore_case = Some(false),
+            _ => return Err(format!("E488: Trailing characters: {}", flag)),
+        }
+    }
+
+    Ok(Command::Substitute {
+        range,
+        pattern,
+        replacement,
+        flags: parsed_flags,
+    })
+}
+
+// Splits the text at the first delimiter that is not escaped with '\',
+// escaped delimiters are unescaped, other escapes are kept for the regex or the replacement
+fn split_at_delimiter(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) if escaped == delimiter => part.push(escaped),
+                Some((_, escaped)) => {
+                    part.push('\\');
+                    part.push(escaped);
+                }
+                None => part.push('\\'),
+            },
+            _ if c == delimiter => return (part, Some(&text[index + c.len_utf8()..])),
+            _ => part.push(c),
+        }
+    }
+
+    (part, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: Address, end: Address) -> Option<Range> {
+        Some(Range { start, end })
+    }
+
+    fn substitute(input: &str) -> (String, String, SubstituteFlags) {
+        match parse_command(input) {
+            Ok(Command::Substitute {
+                pattern,
+                replacement,
+                flags,
+                ..
+            }) => (pattern, replacement, flags),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn flags(global: bool, confirm: bool, ignore_case: Option<bool>) -> SubstituteFlags {
+        SubstituteFlags {
+            global,
+            confirm,
+            ignore_case,
+        }
+    }
+
+    #[test]
+    fn names_and_abbreviations() {
+        for input in ["q", "qu", "quit", " quit  "] {
+            assert_eq!(
+                parse_command(input),
+                Ok(Command::Quit { force: false }),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(parse_command("q!"), Ok(Command::Quit { force: true }));
+        assert_eq!(parse_command("wq"), Ok(Command::WriteQuit { force: false }));
+        assert_eq!(parse_command("wq!"), Ok(Command::WriteQuit { force: true }));
+        assert_eq!(parse_command("noh"), Ok(Command::NoHighlight));
+        assert_eq!(
+            parse_command("quitt"),
+            Err("E492: Not an editor command: quitt".to_string())
+        );
+        assert_eq!(
+            parse_command("n"),
+            Err("E492: Not an editor command: n".to_string())
+        );
+        assert_eq!(parse_command(""), Ok(Command::Empty));
+        assert_eq!(parse_command("  "), Ok(Command::Empty));
+        assert_eq!(
+            parse_command("q now"),
+            Err("E488: Trailing characters: quit".to_string())
+        );
+    }
+
+    #[test]
+    fn arguments() {
+        let write = |filename: Option<&str>, force, create_directories| {
+            Ok(Command::Write {
+                filename: filename.map(str::to_string),
+                force,
+                create_directories,
+            })
+        };
+        assert_eq!(parse_command("w"), write(None, false, false));
+        assert_eq!(
+            parse_command("w! a b.txt "),
+            write(Some("a b.txt"), true, false)
+        );
+        assert_eq!(parse_command("w ++p"), write(None, false, true));
+        assert_eq!(
+            parse_command("w ++p  a.txt"),
+            write(Some("a.txt"), false, true)
+        );
+
+        assert_eq!(
+            parse_command("e! a.txt"),
+            Ok(Command::Edit {
+                filename: "a.txt".to_string(),
+                force: true
+            })
+        );
+        assert_eq!(parse_command("e"), Err("E32: No file name".to_string()));
+        assert_eq!(
+            parse_command("set tabstop=4"),
+            Ok(Command::Set {
+                option: "tabstop".to_string(),
+                value: Some("4".to_string())
+            })
+        );
+        assert_eq!(
+            parse_command("se nu"),
+            Ok(Command::Set {
+                option: "nu".to_string(),
+                value: None
+            })
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            parse_range("%s"),
+            Ok((range(Address::Line(1), Address::Last), "s"))
+        );
+        assert_eq!(
+            parse_range("3,$s"),
+            Ok((range(Address::Line(3), Address::Last), "s"))
+        );
+        assert_eq!(
+            parse_range(".s"),
+            Ok((range(Address::Current, Address::Current), "s"))
+        );
+        assert_eq!(parse_range("s"), Ok((None, "s")));
+        assert_eq!(parse_range("3,"), Err("E14: Invalid address".to_string()));
+        assert_eq!(
+            parse_range("99999999999999999999999"),
+            Err("E14: Invalid address".to_string())
+        );
+
+        // A range without a command goes to its last line
+        assert_eq!(
+            parse_command("12"),
+            Ok(Command::GoToLine(Address::Line(12)))
+        );
+        assert_eq!(parse_command("1,$"), Ok(Command::GoToLine(Address::Last)));
+        assert_eq!(
+            parse_command("1,2w"),
+            Err("E481: No range allowed".to_string())
+        );
+    }
+
+    #[test]
+    fn substitute_delimiters() {
+        assert_eq!(
+            substitute("s/a/b/"),
+            ("a".to_string(), "b".to_string(), flags(false, false, None))
+        );
+        // The closing delimiters can be left out
+        assert_eq!(
+            substitute("s/a/b"),
+            ("a".to_string(), "b".to_string(), flags(false, false, None))
+        );
+        assert_eq!(
+            substitute("s/a"),
+            ("a".to_string(), String::new(), flags(false, false, None))
+        );
+        assert_eq!(
+            substitute("s#a/b#c#g"),
+            ("a/b".to_string(), "c".to_string(), flags(true, false, None))
+        );
+        assert_eq!(
+            substitute("s/a/b/gcI"),
+            (
+                "a".to_string(),
+                "b".to_string(),
+                flags(true, true, Some(false))
+            )
+        );
+        assert_eq!(
+            parse_command("s xaxbx"),
+            Err("E146: Regular expressions can't be delimited by letters".to_string())
+        );
+        assert_eq!(
+            parse_command("s"),
+            Err("E35: No previous regular expression".to_string())
+        );
+        assert_eq!(
+            parse_command("s/a/b/x"),
+            Err("E488: Trailing characters: x".to_string())
+        );
+    }
+
+    #[test]
+    fn substitute_escapes() {
+        // Escaped delimiters are unescaped, other escapes are kept
+        assert_eq!(
+            substitute(r"s/a\/b/c\/d/"),
+            (
+                "a/b".to_string(),
+                "c/d".to_string(),
+                flags(false, false, None)
+            )
+        );
+        assert_eq!(
+            substitute(r"s/\d\+/\r\&/"),
+            (
+                r"\d\+".to_string(),
+                r"\r\&".to_string(),
+                flags(false, false, None)
+            )
+        );
+        assert_eq!(split_at_delimiter(r"a\", '/'), (r"a\".to_string(), None));
+        assert_eq!(
+            split_at_delimiter("a/b/c", '/'),
+            ("a".to_string(), Some("b/c"))
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::input::{KeyCode, KeyEvent};
+
+pub enum CommandLineEvent {
+    // The key did not change the command line
+    None,
+    Edited,
+    Submitted(String),
+    Cancelled,
+}
+
+// A single line prompt on the bottom of the screen, e.g. for ':' commands or '/' searches.
+// Keeps a history of submitted lines that can be recalled with up and down.
+pub struct CommandLine {
+    // The character shown in front of the text, e.g. ':'
+    prefix: char,
+
+    text: String,
+    // Char index of the cursor in text
+    cursor: usize,
+
+    history: Vec<String>,
+    // Index into history while browsing it, None when editing a new line
+    history_index: Option<usize>,
+    // The line that was being edited before browsing the history
+    draft: String,
+}
+
+impl CommandLine {
+    pub fn new() -> CommandLine {
+        CommandLine {
+            prefix: ':',
+            text: String::new(),
+            cursor: 0,
+            history: vec![],
+            history_index: None,
+            draft: String::new(),
+        }
+    }
+
+    pub fn start(&mut self, prefix: char) {
+        self.prefix = prefix;
+        self.set_text(String::new());
+        self.history_index = None;
+        self.draft.clear();
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // The line as it is shown on the screen, including the prefix
+    pub fn display(&self) -> String {
+        format!("{}{}", self.prefix, self.text)
+    }
+
+    // Column of the cursor on the screen, including the prefix
+    pub fn display_cursor(&self) -> usize {
+        self.cursor + 1
+    }
+
+    pub fn process_key(&mut self, key: KeyEvent) -> CommandLineEvent {
+        // Nothing is bound to Ctrl or Alt on the command line
+        if key.modifiers.ctrl || key.modifiers.alt {
+            return CommandLineEvent::None;
+        }
+
+        match key.code {
+            KeyCode::Esc => CommandLineEvent::Cancelled,
+            KeyCode::Enter => {
+                let text = self.text.clone();
+                if !text.is_empty() && self.history.last() != Some(&text) {
+                    self.history.push(text.clone());
+                }
+                CommandLineEvent::Submitted(text)
+            }
+            // Like vim, deleting past the start of an empty line leaves the command line
+            KeyCode::Backspace if self.text.is_empty() => CommandLineEvent::Cancelled,
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.remove_char_at_cursor();
+                CommandLineEvent::Edited
+            }
+            KeyCode::Delete if self.cursor < self.text.chars().count() => {
+                self.remove_char_at_cursor();
+                CommandLineEvent::Edited
+            }
+            KeyCode::Left if self.cursor > 0 => {
+                self.cursor -= 1;
+                CommandLineEvent::Edited
+            }
+            KeyCode::Right if self.cursor < self.text.chars().count() => {
+                self.cursor += 1;
+                CommandLineEvent::Edited
+            }
+            KeyCode::Up => self.history_previous(),
+            KeyCode::Down => self.history_next(),
+            KeyCode::Char(c) => self.insert(c),
+            _ => CommandLineEvent::None,
+        }
+    }
+
+    // Inserts pasted text at the cursor, only its first line as the command line is a single line
+    pub fn paste(&mut self, text: &str) -> CommandLineEvent {
+        let line = text.lines().next().unwrap_or_default();
+        if line.is_empty() {
+            return CommandLineEvent::None;
+        }
+        for c in line.chars() {
+            self.insert(c);
+        }
+        CommandLineEvent::Edited
+    }
+
+    fn insert(&mut self, c: char) -> CommandLineEvent {
+        let index = self.byte_index(self.cursor);
+        self.text.insert(index, c);
+        self.cursor += 1;
+        CommandLineEvent::Edited
+    }
+
+    fn history_previous(&mut self) -> CommandLineEvent {
+        let index = match self.history_index {
+            Some(0) => return CommandLineEvent::None,
+            Some(i) => i - 1,
+            None if self.history.is_empty() => return CommandLineEvent::None,
+            None => {
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            }
+        };
+
+        self.history_index = Some(index);
+        self.set_text(self.history[index].clone());
+        CommandLineEvent::Edited
+    }
+
+    fn history_next(&mut self) -> CommandLineEvent {
+        match self.history_index {
+            None => CommandLineEvent::None,
+            Some(i) if i + 1 < self.history.len() => {
+                self.history_index = Some(i + 1);
+                self.set_text(self.history[i + 1].clone());
+                CommandLineEvent::Edited
+            }
+            Some(_) => {
+                // Moving past the newest entry restores the line we were editing
+                self.history_index = None;
+                self.set_text(self.draft.clone());
+                CommandLineEvent::Edited
+            }
+        }
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.cursor = text.chars().count();
+        self.text = text;
+    }
+
+    fn remove_char_at_cursor(&mut self) {
+        let index = self.byte_index(self.cursor);
+        self.text.remove(index);
+    }
+
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(char_index)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+}
@@ -1,30 +1,55 @@
 use crate::logger::LogLevel;
 use once_cell::sync::Lazy;
-use std::env;
+use std::{env, time::Duration};
 
 // Version info
-pub const VERSION: &'static str = "0.0.1";
-
-// Whether to use blocking read for stdin
-// Blocking read: pressing esc will not be read until a key is pressed as it could be part of an escape sequence
-// Non-blocking read: we will consistently read 0 bytes until a key is pressed which could lead to consistent rerendering
-// Using non-blocking read for now to allow for esc to be read
-pub const BLOCKING_READ: Lazy<bool> = Lazy::new(|| {
-    env::var("MIV_BLOCKING_READ")
+pub const VERSION: &str = "0.0.1";
+
+// How long to wait for the rest of an escape sequence before an ESC is taken as the Esc key
+pub static ESCAPE_TIMEOUT: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_millis(
+        env::var("MIV_ESCAPE_TIMEOUT")
+            .unwrap_or("25".to_string())
+            .parse()
+            .unwrap(),
+    )
+});
+
+// Whether to ask the terminal for the Kitty keyboard protocol or modifyOtherKeys,
+// which tell apart keys like Ctrl-I and Tab
+pub static KEYBOARD_ENHANCEMENT: Lazy<bool> = Lazy::new(|| {
+    env::var("MIV_KEYBOARD_ENHANCEMENT")
         .unwrap_or("false".to_string())
         .parse()
         .unwrap()
 });
 
+// Whether to draw on the alternate screen, so the shell is left as it was when miv exits
+pub static ALTERNATE_SCREEN: Lazy<bool> = Lazy::new(|| {
+    env::var("MIV_ALTERNATE_SCREEN")
+        .unwrap_or("true".to_string())
+        .parse()
+        .unwrap()
+});
+
 // Log level
 pub static LOG_LEVEL: Lazy<LogLevel> = Lazy::new(|| {
     LogLevel::from_string_or_default(&env::var("MIV_LOG_LEVEL").unwrap_or("INFO".to_string()))
 });
 
 // Tab width
-pub const TAB_WIDTH: Lazy<usize> = Lazy::new(|| {
+pub static TAB_WIDTH: Lazy<usize> = Lazy::new(|| {
     env::var("MIV_TAB_WIDTH")
         .unwrap_or("4".to_string())
         .parse()
         .unwrap()
 });
+
+// Number of times quit has to be pressed to discard unsaved changes
+pub const QUIT_TIMES: usize = 3;
+
+// How long a message stays in the message bar
+pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Number of lines the mouse wheel scrolls
+pub const SCROLL_LINES: usize = 3;
@@ -1,10 +1,430 @@
+use crate::history::{Edit, History};
+use ropey::{Rope, RopeBuilder, RopeSlice};
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+// The text is stored in a rope, so edits and line lookups are O(log n) in the size of the file.
+// Lines are separated by '\n', the trailing newline of a file is not part of the rope.
+// Columns are char indices into a line.
 #[derive(Clone)]
 pub struct Content {
-    pub lines: Vec<String>,
+    text: Rope,
+
+    // A rope always has at least one (possibly empty) line,
+    // this tracks whether the content has no lines at all, e.g. for an empty file
+    empty: bool,
+
+    history: History,
 }
 
 impl Content {
     pub fn new() -> Content {
-        Content { lines: vec![] }
+        Content {
+            text: Rope::new(),
+            empty: true,
+            history: History::new(),
+        }
+    }
+
+    pub fn from_text(text: &str) -> Content {
+        Content::from_rope(Rope::from_str(text))
+    }
+
+    // The file is read straight into the rope, so a large file is not held in memory twice
+    pub fn from_file(filename: &str) -> Result<Content, io::Error> {
+        let text = Rope::from_reader(BufReader::new(File::open(filename)?))?;
+        Ok(Content::from_rope(text))
+    }
+
+    fn from_rope(text: Rope) -> Content {
+        let mut content = Content::new();
+        if text.len_chars() == 0 {
+            return content;
+        }
+
+        content.text = normalize_line_endings(text);
+        let len = content.text.len_chars();
+        if content.text.char(len - 1) == '\n' {
+            content.text.remove(len - 1..);
+        }
+        content.empty = false;
+        content
+    }
+
+    // A copy of the whole text, copies of a rope share their chunks so this is cheap
+    pub fn text(&self) -> Rope {
+        self.text.clone()
+    }
+
+    pub fn line_count(&self) -> usize {
+        match self.empty {
+            true => 0,
+            false => self.text.len_lines(),
+        }
+    }
+
+    // The row of the last line, the cursor in empty content is on row 0 like on an empty line
+    pub fn last_row(&self) -> usize {
+        self.line_count().saturating_sub(1)
+    }
+
+    // Returns the line without its line break
+    pub fn line(&self, row: usize) -> RopeSlice<'_> {
+        let line = self.text.line(row);
+        match line.len_chars() > 0 && line.char(line.len_chars() - 1) == '\n' {
+            true => line.slice(..line.len_chars() - 1),
+            false => line,
+        }
+    }
+
+    pub fn line_len(&self, row: usize) -> usize {
+        match row < self.line_count() {
+            true => self.line(row).len_chars(),
+            false => 0,
+        }
+    }
+
+    pub fn insert_char(&mut self, row: usize, column: usize, c: char) {
+        self.edit(Edit::Insert {
+            row,
+            column,
+            text: c.to_string(),
+        });
+    }
+
+    pub fn insert_line(&mut self, row: usize, line: String) {
+        if row < self.line_count() {
+            self.edit(Edit::Insert {
+                row,
+                column: 0,
+                text: line + "\n",
+            });
+        } else if row > 0 {
+            self.edit(Edit::Insert {
+                row: row - 1,
+                column: self.line_len(row - 1),
+                text: "\n".to_string() + &line,
+            });
+        } else {
+            self.edit(Edit::Insert {
+                row,
+                column: 0,
+                text: line,
+            });
+        }
+    }
+
+    // Splits the line at the given column, the remainder becomes the next line
+    pub fn split_line(&mut self, row: usize, column: usize) {
+        self.edit(Edit::Insert {
+            row,
+            column,
+            text: "\n".to_string(),
+        });
+    }
+
+    // Appends the next line to the given line
+    pub fn join_lines(&mut self, row: usize) {
+        if row + 1 < self.line_count() {
+            self.edit(Edit::Delete {
+                row,
+                column: self.line_len(row),
+                text: "\n".to_string(),
+            });
+        }
+    }
+
+    // Replaces the text between the columns of the row, the new text can contain line breaks
+    pub fn replace(&mut self, row: usize, start: usize, end: usize, text: &str) {
+        if start < end {
+            let deleted = self.line(row).slice(start..end).to_string();
+            self.edit(Edit::Delete {
+                row,
+                column: start,
+                text: deleted,
+            });
+        }
+        if !text.is_empty() {
+            self.edit(Edit::Insert {
+                row,
+                column: start,
+                text: text.to_string(),
+            });
+        }
+    }
+
+    // Removes the text from the start up to the end position, which can be on a later row
+    pub fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) {
+        let (row, column) = start;
+        let start = self.text.line_to_char(row) + column;
+        let end = self.text.line_to_char(end.0) + end.1;
+        if start < end {
+            let text = self.text.slice(start..end).to_string();
+            self.edit(Edit::Delete { row, column, text });
+        }
+    }
+
+    // Groups all following edits into a single undo step until end_undo_group is called
+    pub fn begin_undo_group(&mut self, cursor: (usize, usize)) {
+        self.history.begin_group(cursor);
+    }
+
+    pub fn end_undo_group(&mut self, cursor: (usize, usize)) {
+        self.history.end_group(cursor);
+    }
+
+    // Reverts the last undo step and returns the cursor position from before it
+    pub fn undo(&mut self) -> Option<(usize, usize)> {
+        let step = self.history.pop_undo()?;
+        for edit in step.edits.iter().rev() {
+            self.apply(&edit.inverse());
+        }
+        // Undoing the first insert into empty content leaves it without any lines again
+        if step.was_empty {
+            self.empty = true;
+        }
+        Some(step.undo_cursor())
+    }
+
+    // Reapplies the last undone step and returns the cursor position from after it
+    pub fn redo(&mut self) -> Option<(usize, usize)> {
+        let step = self.history.pop_redo()?;
+        for edit in step.edits.iter() {
+            self.apply(edit);
+        }
+        Some(step.redo_cursor())
+    }
+
+    fn edit(&mut self, edit: Edit) {
+        let was_empty = self.empty;
+        self.apply(&edit);
+        self.history.record(edit, was_empty);
+    }
+
+    // Whether the content changed since it was last loaded or saved
+    pub fn modified(&self) -> bool {
+        self.history.is_modified()
+    }
+
+    // Remembers the current content as the one in the file
+    pub fn mark_saved(&mut self) {
+        self.history.mark_saved();
+    }
+
+    fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { row, column, text } => {
+                // Inserting into an empty buffer creates its first line
+                self.empty = false;
+
+                let index = self.text.line_to_char(*row) + column;
+                self.text.insert(index, text);
+            }
+            Edit::Delete { row, column, text } => {
+                let index = self.text.line_to_char(*row) + column;
+                self.text.remove(index..index + text.chars().count());
+            }
+        }
+    }
+
+    // Writes the content to a temporary file next to the target and renames it over the target,
+    // so a failed write never leaves a truncated file behind.
+    // A file we may not write to is only replaced when forced, renaming only needs the directory.
+    // Returns the number of bytes written.
+    pub fn save(&self, filename: &str, force: bool) -> Result<usize, io::Error> {
+        // Resolve symlinks so we replace the file they point to and not the link itself
+        let path = fs::canonicalize(filename).unwrap_or(PathBuf::from(filename));
+        let path = path.as_path();
+        if !force && !is_writable(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file is read-only (add ! to override)",
+            ));
+        }
+        let temp_path = path.with_file_name(format!(
+            ".{}.miv-tmp",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        let result = self.write_and_rename(path, &temp_path);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn write_and_rename(&self, path: &Path, temp_path: &Path) -> Result<usize, io::Error> {
+        let mut writer = BufWriter::new(File::create(temp_path)?);
+        for chunk in self.text.chunks() {
+            writer.write_all(chunk.as_bytes())?;
+        }
+
+        let mut bytes = self.text.len_bytes();
+        if !self.empty {
+            writer.write_all(b"\n")?;
+            bytes += 1;
+        }
+        writer.into_inner()?.sync_all()?;
+
+        // Keep the permissions of the file we are replacing
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(temp_path, metadata.permissions())?;
+        }
+
+        fs::rename(temp_path, path)?;
+        Ok(bytes)
+    }
+}
+
+// Windows line endings are normalized, the content only knows about '\n'.
+// Only text with a '\r' is copied, chunk by chunk.
+fn normalize_line_endings(text: Rope) -> Rope {
+    if !text.chunks().any(|chunk| chunk.contains('\r')) {
+        return text;
+    }
+
+    let mut builder = RopeBuilder::new();
+    // A "\r\n" can be split between two chunks
+    let mut carriage_return = false;
+    for chunk in text.chunks() {
+        if carriage_return && !chunk.starts_with('\n') {
+            builder.append("\r");
+        }
+        carriage_return = chunk.ends_with('\r');
+        let chunk = chunk.strip_suffix('\r').unwrap_or(chunk);
+        builder.append(&chunk.replace("\r\n", "\n"));
+    }
+    if carriage_return {
+        builder.append("\r");
+    }
+    builder.finish()
+}
+
+// Whether the file does not exist yet or we may write to it.
+// The mode is checked too, as root may write to any file.
+fn is_writable(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return true;
+    };
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return true;
+    };
+    !metadata.permissions().readonly() && unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("miv-content-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn lines(content: &Content) -> Vec<String> {
+        (0..content.line_count())
+            .map(|row| content.line(row).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn trailing_newline() {
+        assert_eq!(Content::from_text("").line_count(), 0);
+        assert_eq!(lines(&Content::from_text("\n")), [""]);
+        assert_eq!(lines(&Content::from_text("a\nb")), ["a", "b"]);
+        assert_eq!(lines(&Content::from_text("a\nb\n")), ["a", "b"]);
+        assert_eq!(lines(&Content::from_text("a\n\n")), ["a", ""]);
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(lines(&Content::from_text("a\r\nb\r\n")), ["a", "b"]);
+        assert_eq!(lines(&Content::from_text("a\rb\r\n\r")), ["a\rb", "\r"]);
+
+        // Long enough for the rope to split some "\r\n" between two chunks
+        let text: String = (0..2000)
+            .map(|i| format!("{}\r\n", "x".repeat(i % 7)))
+            .collect();
+        let content = Content::from_text(&text);
+        assert_eq!(
+            content.text().to_string() + "\n",
+            text.replace("\r\n", "\n")
+        );
+    }
+
+    #[test]
+    fn edits() {
+        let mut content = Content::from_text("ab\ncd");
+        content.insert_char(0, 1, 'x');
+        content.split_line(1, 1);
+        assert_eq!(lines(&content), ["axb", "c", "d"]);
+        content.join_lines(0);
+        content.replace(0, 1, 3, "y\nz");
+        assert_eq!(lines(&content), ["ay", "zc", "d"]);
+        content.delete_range((0, 1), (1, 1));
+        assert_eq!(lines(&content), ["ac", "d"]);
+
+        while content.undo().is_some() {}
+        assert_eq!(lines(&content), ["ab", "cd"]);
+        assert!(!content.modified());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let directory = test_directory("save_and_load");
+        let path = directory.join("file.txt");
+        let filename = path.to_str().unwrap();
+
+        for (text, saved) in [
+            ("a\nb", "a\nb\n"),
+            ("a\r\nb\r\n", "a\nb\n"),
+            ("\n", "\n"),
+            ("", ""),
+        ] {
+            let content = Content::from_text(text);
+            assert_eq!(content.save(filename, false).unwrap(), saved.len());
+            assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+            let loaded = Content::from_file(filename).unwrap();
+            assert_eq!(lines(&loaded), lines(&content), "{:?}", text);
+        }
+
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        let error = Content::from_file(filename).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn save_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = test_directory("save_keeps_permissions");
+        let path = directory.join("script.sh");
+        let filename = path.to_str().unwrap();
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        Content::from_text("exit").save(filename, false).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+
+        // Nothing but the file itself is left behind
+        let entries = fs::read_dir(&directory).unwrap().count();
+        assert_eq!(entries, 1);
+        fs::remove_dir_all(&directory).unwrap();
     }
 }
@@ -0,0 +1,88 @@
+use crate::logger::{LogLevel, Logger};
+use crate::{input, stdin_raw_mode, warn};
+use ropey::Rope;
+use std::backtrace::Backtrace;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, TryLockError};
+use std::{env, panic, process};
+
+// The file name and text of a buffer with unsaved changes, written to a recovery file on a crash
+static UNSAVED_BUFFER: Mutex<Option<(String, Rope)>> = Mutex::new(None);
+
+// Remembers the buffer for the panic hook, None when there are no unsaved changes
+pub fn set_unsaved_buffer(buffer: Option<(String, Rope)>) {
+    *UNSAVED_BUFFER.lock().unwrap_or_else(|e| e.into_inner()) = buffer;
+}
+
+// Installs a panic hook that puts the terminal back into the state it was in before miv started,
+// logs the panic with a backtrace and writes unsaved changes to a recovery file before exiting.
+// Exiting right away means no Drop runs after the terminal is restored and the message is printed.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+
+        // The panic can happen while the logger is locked, so we do not wait for it
+        if let Ok(mut logger) = Logger::global().try_lock() {
+            let backtrace = Backtrace::force_capture();
+            logger.log(LogLevel::Fatal, &format!("{}\n{}", info, backtrace));
+        }
+
+        default_hook(info);
+        if let Some(path) = write_recovery_file() {
+            // Unlike eprintln, this does not panic again if stderr is gone
+            let _ = writeln!(
+                io::stderr(),
+                "miv: unsaved changes were written to {}",
+                path.display()
+            );
+        }
+        process::exit(101);
+    }));
+}
+
+// Writes unsaved changes to a recovery file when the editor cannot go on,
+// e.g. because its terminal was closed and no more keys can be read
+pub fn recover_unsaved_buffer() {
+    if let Some(path) = write_recovery_file() {
+        warn!("Unsaved changes were written to {}", path.display());
+    }
+}
+
+fn restore_terminal() {
+    // The keyboard mode belongs to the alternate screen, so it is restored before leaving it
+    input::restore_keyboard_mode();
+    stdin_raw_mode::restore_terminal_mode();
+
+    // Reset the colors, clear what was drawn, show the cursor and leave the alternate screen
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(b"\x1b[m\x1b[2J\x1b[H\x1b[?25h\x1b[?1049l");
+    let _ = stdout.flush();
+}
+
+// Writes the unsaved buffer next to its file, or into the temporary directory if that fails.
+// Returns where it was written.
+fn write_recovery_file() -> Option<PathBuf> {
+    let (filename, text) = match UNSAVED_BUFFER.try_lock() {
+        Ok(mut buffer) => buffer.take()?,
+        Err(TryLockError::Poisoned(e)) => e.into_inner().take()?,
+        Err(TryLockError::WouldBlock) => return None,
+    };
+
+    let path = PathBuf::from(format!("{}.miv-recovery", filename));
+    let fallback = env::temp_dir().join(path.file_name()?);
+    [path, fallback]
+        .into_iter()
+        .find(|path| write_text(path, &text).is_ok())
+}
+
+fn write_text(path: &Path, text: &Rope) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    text.write_to(&mut writer)?;
+    if text.len_chars() > 0 {
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
@@ -1,27 +1,62 @@
+use crate::backend::{AnsiBackend, Backend};
+use crate::command::{parse_command, Address, Command, Range, SubstituteFlags};
+use crate::command_line::{CommandLine, CommandLineEvent};
+use crate::constants::{MESSAGE_TIMEOUT, QUIT_TIMES, SCROLL_LINES};
 use crate::content::Content;
-use crate::input::{Input, Key};
-use crate::trace;
-use crate::{log, screen::Screen};
-use std::{
-    fs::File,
-    io::{self, BufRead, Error},
-};
-
-// TODO: This should be part of the input module
-const fn ctrl_key(k: char) -> u8 {
-    (k as u8) & 0x1f
+use crate::crash;
+use crate::event::{Event, EventSource};
+use crate::file_type::file_type;
+use crate::grapheme;
+use crate::input::{Input, KeyCode, KeyEvent, Modifiers, MouseButton, MouseEvent, MouseKind};
+use crate::layout::LineLayout;
+use crate::log;
+use crate::options::Options;
+use crate::screen::{Screen, Status, View};
+use crate::search::{self, build_regex, Direction};
+use crate::substitute::{SubstituteMatch, Substitution};
+use crate::{error, trace, warn};
+use regex::Regex;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::{borrow::Cow, fs, time::Instant};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Command,
+    Search,
+    // Asking whether to replace a match of ':s///c'
+    Confirm,
+    // Selecting text, from the selection anchor to the cursor
+    Visual,
 }
 
-pub struct Editor {
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Command => "COMMAND",
+            Mode::Search => "SEARCH",
+            Mode::Confirm => "CONFIRM",
+            Mode::Visual => "VISUAL",
+        }
+    }
+}
+
+// Reads events from the terminal and draws to it, unless another event source and backend are given
+pub struct Editor<I: EventSource = Input, B: Backend = AnsiBackend> {
     // Struct fields are dropped in the same order of declaration,
     // so screen will be dropped before input.
     // This ensures raw mode is still enabled while we clean up the screen.
-    screen: Screen,
-    input: Input,
+    screen: Screen<B>,
+    input: I,
 
     // TODO: This should live somewhere else
     content: Content,
-    filename: String,
+    // None for a buffer that was not opened from a file and has not been written yet
+    filename: Option<String>,
 
     // NOTE: These are usize and therefore cannot be negative, even in calculations
     cursor_row: usize,
@@ -29,31 +64,83 @@ pub struct Editor {
 
     row_offset: usize,
     column_offset: usize,
+
+    mode: Mode,
+
+    // Remaining quit presses before unsaved changes are discarded
+    quit_times: usize,
+    should_quit: bool,
+
+    command_line: CommandLine,
+    search_line: CommandLine,
+    // Shown in the message bar until MESSAGE_TIMEOUT has passed
+    message: Option<(String, Instant)>,
+
+    options: Options,
+
+    // The last searched pattern, repeated with 'n' and 'N'
+    search_regex: Option<Regex>,
+    search_direction: Direction,
+    // The pattern typed so far while searching
+    incremental_regex: Option<Regex>,
+    // Whether matches of the last searched pattern are highlighted
+    highlight_search: bool,
+    // Cursor and offsets from before the search started, restored when it is cancelled
+    saved_position: (usize, usize, usize, usize),
+
+    // Row and column where the selection of visual mode started, the cursor is its other end
+    selection_anchor: (usize, usize),
+
+    // A ':s///c' waiting for confirmation of the current match
+    substitution: Option<(Substitution, SubstituteMatch)>,
 }
 
 impl Editor {
     pub fn new() -> Result<Editor, Error> {
-        log!("Initializing editor");
+        // The terminal keeps a keyboard mode for each screen,
+        // so the alternate screen is entered before the input negotiates one
+        let backend = AnsiBackend::new();
+        // Raw mode has to be enabled before the screen asks the terminal for its size
+        let input = Input::new()?;
+        let screen = Screen::with_backend(backend)?;
+        Ok(Editor::with_io(input, screen))
+    }
+}
 
-        let input = Input::new();
-        let screen = Screen::new().unwrap();
+impl<I: EventSource, B: Backend> Editor<I, B> {
+    pub fn with_io(input: I, screen: Screen<B>) -> Editor<I, B> {
+        log!("Initializing editor");
 
-        Ok(Editor {
+        Editor {
             screen,
             input,
             content: Content::new(),
-            filename: "logs/test-file.txt".to_string(),
+            filename: None,
             cursor_row: 0,
             cursor_column: 0,
             row_offset: 0,
             column_offset: 0,
-        })
+            mode: Mode::Normal,
+            quit_times: QUIT_TIMES,
+            should_quit: false,
+            command_line: CommandLine::new(),
+            search_line: CommandLine::new(),
+            message: None,
+            options: Options::new(),
+            search_regex: None,
+            search_direction: Direction::Forward,
+            incremental_regex: None,
+            highlight_search: false,
+            saved_position: (0, 0, 0, 0),
+            selection_anchor: (0, 0),
+            substitution: None,
+        }
     }
 
     pub fn editor_open_with_file(&mut self, filename: String) {
         log!("Opening editor with file: {}", filename);
 
-        self.filename = filename;
+        self.filename = Some(filename);
         self.editor_open_file();
 
         self.editor_open();
@@ -61,63 +148,977 @@ impl Editor {
 
     pub fn editor_open(&mut self) {
         // Refresh screen to show the initial content
-        self.screen.editor_refresh_screen(
-            self.content.clone(),
+        self.refresh_screen();
+
+        while !self.should_quit {
+            // The panic hook writes unsaved changes to a recovery file
+            crash::set_unsaved_buffer(self.content.modified().then(|| {
+                let filename = self.filename.as_deref().unwrap_or("untitled");
+                (filename.to_string(), self.content.text())
+            }));
+
+            // Wake up when the message has to be removed, otherwise wait for the next event
+            let timeout = self
+                .message
+                .as_ref()
+                .map(|(_, time)| MESSAGE_TIMEOUT.saturating_sub(time.elapsed()));
+
+            let Some(event) = self.input.read_event(timeout) else {
+                break;
+            };
+            let key = match event {
+                Event::Key(key) => key,
+                Event::Resize => {
+                    if self.screen.update_size() {
+                        self.handle_resize();
+                    }
+                    continue;
+                }
+                Event::Timeout => {
+                    if self.clear_expired_message() {
+                        self.refresh_screen();
+                    }
+                    continue;
+                }
+                Event::Paste(text) => {
+                    self.process_paste(&text);
+                    self.quit_times = QUIT_TIMES;
+                    self.refresh_screen();
+                    continue;
+                }
+                Event::Mouse(mouse) => {
+                    self.process_mouse(mouse);
+                    self.refresh_screen();
+                    continue;
+                }
+            };
+
+            match key {
+                _ if key == KeyEvent::ctrl('q') || key == KeyEvent::ctrl('c') => {
+                    if self.try_quit() {
+                        break;
+                    }
+                    self.refresh_screen();
+                    continue;
+                }
+                _ if key == KeyEvent::ctrl('s') => {
+                    self.save(None, false, false);
+                }
+                _ => match self.mode {
+                    Mode::Normal => self.process_normal_key(key),
+                    Mode::Insert => self.process_insert_key(key),
+                    Mode::Command => self.process_command_key(key),
+                    Mode::Search => self.process_search_key(key),
+                    Mode::Confirm => self.process_confirm_key(key),
+                    Mode::Visual => self.process_visual_key(key),
+                },
+            }
+            self.quit_times = QUIT_TIMES;
+
+            // Refresh screen to show the updated content
+            self.refresh_screen();
+        }
+    }
+
+    // Clamps the offsets to the new window size and redraws
+    fn handle_resize(&mut self) {
+        // Show as many lines as fit when the window grew
+        self.row_offset = self.row_offset.min(
+            self.content
+                .line_count()
+                .saturating_sub(self.screen.get_text_height()),
+        );
+        self.scroll();
+        self.refresh_screen();
+    }
+
+    // Returns whether the editor should exit,
+    // unsaved changes are only discarded after quit has been pressed QUIT_TIMES in a row
+    fn try_quit(&mut self) -> bool {
+        if !self.content.modified() {
+            log!("Exiting");
+            return true;
+        }
+
+        self.quit_times -= 1;
+        if self.quit_times == 0 {
+            log!("Exiting and discarding unsaved changes");
+            return true;
+        }
+
+        warn!(
+            "File has unsaved changes, press quit {} more times to discard them",
+            self.quit_times
+        );
+        self.set_message(format!(
+            "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+            self.quit_times
+        ));
+        false
+    }
+
+    // Saves to the given file or the file being edited, returns whether saving succeeded.
+    // Read-only files are only replaced when forced and missing directories only created when asked to.
+    fn save(&mut self, filename: Option<&str>, force: bool, create_directories: bool) -> bool {
+        let Some(filename) = filename.or(self.filename.as_deref()).map(str::to_string) else {
+            self.set_message("E32: No file name".to_string());
+            return false;
+        };
+
+        // Writing a copy to another file keeps the buffer modified
+        let copy = self.filename.as_ref().is_some_and(|name| *name != filename);
+
+        let result = match (create_directories, missing_directory(&filename)) {
+            (true, Some(directory)) => {
+                fs::create_dir_all(directory).and_then(|_| self.content.save(&filename, force))
+            }
+            _ => self.content.save(&filename, force),
+        };
+        match result {
+            Ok(bytes) => {
+                log!("{} bytes written to {}", bytes, filename);
+                // An unnamed buffer takes the name of the file it is written to, like in vim
+                self.filename.get_or_insert_with(|| filename.clone());
+                if !copy {
+                    self.content.mark_saved();
+                }
+                self.set_message(format!("\"{}\" {}B written", filename, bytes));
+                true
+            }
+            Err(e) => {
+                error!("Failed to save {}: {}", filename, e);
+                let reason = match missing_directory(&filename) {
+                    Some(directory) => format!(
+                        "directory \"{}\" does not exist, :w ++p creates it",
+                        directory.display()
+                    ),
+                    None => describe_error(&e),
+                };
+                self.set_message(format!("Can't write \"{}\": {}", filename, reason));
+                false
+            }
+        }
+    }
+
+    fn set_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+
+    // Returns whether a message was removed
+    fn clear_expired_message(&mut self) -> bool {
+        match &self.message {
+            Some((_, time)) if time.elapsed() >= MESSAGE_TIMEOUT => {
+                self.message = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn refresh_screen(&mut self) {
+        let (new_column_offset, new_cursor_x) = self.get_horizontal_cursor_position(
             self.cursor_row,
             self.cursor_column,
-            self.row_offset,
             self.column_offset,
         );
 
-        loop {
-            // TODO: This should probably be moved to a function
-            let key = self.input.read_key();
-            match key {
-                Key::None => {
-                    // None means we did not read a key
-                    // We should not refresh the screen as we did not read a key
-                }
-                Key::Other(c) if c == ctrl_key('q') => {
-                    log!("Ctrl Q, Exiting");
-                    break;
-                }
-                Key::Other(c) if c == ctrl_key('c') => {
-                    log!("Ctrl C, Exiting");
-                    break;
+        // The command line replaces the message while it is open
+        let message = match (self.mode, &self.message) {
+            (Mode::Command, _) => Some((
+                self.command_line.display(),
+                Some(self.command_line.display_cursor()),
+            )),
+            (Mode::Search, _) => Some((
+                self.search_line.display(),
+                Some(self.search_line.display_cursor()),
+            )),
+            (Mode::Confirm, _) => Some((self.confirm_prompt(), None)),
+            (_, Some((message, _))) => Some((message.clone(), None)),
+            _ => None,
+        };
+
+        let status = Status {
+            filename: self.filename.as_deref().unwrap_or("[No Name]"),
+            modified: self.content.modified(),
+            file_type: file_type(self.filename.as_deref().unwrap_or_default()),
+            mode: self.mode.name(),
+            message: message
+                .as_ref()
+                .map(|(text, cursor)| (text.as_str(), *cursor)),
+        };
+
+        // Matches are highlighted while typing a search and afterwards until ':nohlsearch'
+        let highlight = match (self.mode, &self.substitution) {
+            (Mode::Search, _) => self.incremental_regex.as_ref(),
+            (Mode::Confirm, Some((substitution, _))) => Some(substitution.regex()),
+            _ if self.highlight_search => self.search_regex.as_ref(),
+            _ => None,
+        };
+        let current_match = match (self.mode, &self.substitution) {
+            (Mode::Confirm, Some((_, found))) => Some((found.row, found.start, found.end)),
+            _ => None,
+        };
+        let selection = match self.mode {
+            Mode::Visual => Some(self.selection()),
+            _ => None,
+        };
+
+        let view = View {
+            cursor_row: self.cursor_row,
+            cursor_column: new_cursor_x,
+            row_offset: self.row_offset,
+            column_offset: new_column_offset,
+            highlight,
+            current_match,
+            selection,
+        };
+
+        self.screen
+            .editor_refresh_screen(&self.content, &view, &status);
+    }
+
+    fn process_normal_key(&mut self, key: KeyEvent) {
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('u'), Modifiers::NONE) => self.undo(),
+            (KeyCode::Char('r'), Modifiers::CTRL) => self.redo(),
+            (KeyCode::Char(':'), Modifiers::NONE) => {
+                self.mode = Mode::Command;
+                self.command_line.start(':');
+            }
+            (KeyCode::Char('/'), Modifiers::NONE) => self.start_search(Direction::Forward),
+            (KeyCode::Char('?'), Modifiers::NONE) => self.start_search(Direction::Backward),
+            (KeyCode::Char('n'), Modifiers::NONE) => self.repeat_search(false),
+            (KeyCode::Char('N'), Modifiers::NONE) => self.repeat_search(true),
+            (KeyCode::Char('i'), Modifiers::NONE) => self.enter_insert_mode(),
+            (KeyCode::Char('v'), Modifiers::NONE) => self.enter_visual_mode(),
+            (KeyCode::Char('a'), Modifiers::NONE) => {
+                self.enter_insert_mode();
+                if self.cursor_column < self.content.line_len(self.cursor_row) {
+                    self.cursor_column = grapheme::next_boundary(
+                        self.content.line(self.cursor_row),
+                        self.cursor_column,
+                    );
                 }
-                _ => {
-                    // We read a movement key and should refresh the screen
-                    self.move_cursor(key);
+                self.scroll();
+            }
+            (KeyCode::Char('o'), Modifiers::NONE) => {
+                self.enter_insert_mode();
+                self.content.insert_line(self.cursor_row + 1, String::new());
+                self.cursor_row += 1;
+                self.cursor_column = 0;
+                self.scroll();
+            }
+            (KeyCode::Char('O'), Modifiers::NONE) => {
+                self.enter_insert_mode();
+                self.content.insert_line(self.cursor_row, String::new());
+                self.cursor_column = 0;
+                self.scroll();
+            }
+            (code, Modifiers::NONE) => self.move_cursor(code),
+            _ => {}
+        }
+    }
+
+    fn process_insert_key(&mut self, key: KeyEvent) {
+        if key.modifiers != Modifiers::NONE {
+            return;
+        }
+
+        // Only moving up and down keeps the column remembered from a longer line
+        if !matches!(
+            key.code,
+            KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown
+        ) {
+            self.reset_cursor();
+        }
 
-                    let (new_column_offset, new_cursor_x) = self.get_horizontal_cursor_position(
-                        &self.content.lines[self.cursor_row],
+        match key.code {
+            KeyCode::Esc => {
+                log!("Leaving insert mode");
+                self.content
+                    .end_undo_group((self.cursor_row, self.cursor_column));
+                self.mode = Mode::Normal;
+                // Like vim, the cursor moves back onto the last inserted character
+                if self.cursor_row < self.content.line_count() {
+                    self.cursor_column = grapheme::previous_boundary(
+                        self.content.line(self.cursor_row),
                         self.cursor_column,
-                        self.column_offset,
                     );
-                    self.screen.editor_refresh_screen(
-                        self.content.clone(),
-                        self.cursor_row,
-                        new_cursor_x,
-                        self.row_offset,
-                        new_column_offset,
+                }
+                self.reset_cursor();
+            }
+            KeyCode::Enter => self.insert_newline(),
+            KeyCode::Backspace => self.delete_char_before_cursor(),
+            KeyCode::Delete => self.delete_char_at_cursor(),
+            KeyCode::Tab => self.insert_char('\t'),
+            KeyCode::Char(c) => self.insert_char(c),
+            KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::PageUp
+            | KeyCode::PageDown => self.move_cursor(key.code),
+            _ => {}
+        }
+    }
+
+    fn process_command_key(&mut self, key: KeyEvent) {
+        let event = self.command_line.process_key(key);
+        self.process_command_event(event);
+    }
+
+    fn process_command_event(&mut self, event: CommandLineEvent) {
+        match event {
+            CommandLineEvent::Submitted(text) => {
+                self.mode = Mode::Normal;
+                if let Err(message) = parse_command(&text).and_then(|c| self.execute_command(c)) {
+                    self.set_message(message);
+                }
+            }
+            CommandLineEvent::Cancelled => self.mode = Mode::Normal,
+            CommandLineEvent::Edited | CommandLineEvent::None => {}
+        }
+    }
+
+    // Pasted text is never taken as commands, it is inserted as it is
+    fn process_paste(&mut self, text: &str) {
+        match self.mode {
+            Mode::Normal | Mode::Insert => self.paste(text),
+            Mode::Command => {
+                let event = self.command_line.paste(text);
+                self.process_command_event(event);
+            }
+            Mode::Search => {
+                let event = self.search_line.paste(text);
+                self.process_search_event(event);
+            }
+            Mode::Confirm | Mode::Visual => {}
+        }
+    }
+
+    fn enter_visual_mode(&mut self) {
+        log!("Entering visual mode");
+        self.mode = Mode::Visual;
+        self.selection_anchor = (self.cursor_row, self.cursor_column);
+    }
+
+    fn process_visual_key(&mut self, key: KeyEvent) {
+        match (key.code, key.modifiers) {
+            (KeyCode::Esc | KeyCode::Char('v'), Modifiers::NONE) => self.mode = Mode::Normal,
+            (KeyCode::Char('d' | 'x'), Modifiers::NONE) => self.delete_selection(),
+            (code, Modifiers::NONE) => self.move_cursor(code),
+            _ => {}
+        }
+    }
+
+    // The first and the last selected character, both on a character of their row
+    fn selection(&self) -> ((usize, usize), (usize, usize)) {
+        let anchor = self.clamp_position(self.selection_anchor);
+        let cursor = self.clamp_position((self.cursor_row, self.cursor_column));
+        match anchor <= cursor {
+            true => (anchor, cursor),
+            false => (cursor, anchor),
+        }
+    }
+
+    fn delete_selection(&mut self) {
+        let (start, (end_row, end_column)) = self.selection();
+        // The end is exclusive for the content, on an empty line the line break is deleted
+        let end = match self.content.line_len(end_row) {
+            0 if end_row + 1 < self.content.line_count() => (end_row + 1, 0),
+            0 => (end_row, 0),
+            _ => (
+                end_row,
+                grapheme::next_boundary(self.content.line(end_row), end_column),
+            ),
+        };
+        self.content.delete_range(start, end);
+
+        self.mode = Mode::Normal;
+        (self.cursor_row, self.cursor_column) = start;
+        self.reset_cursor();
+        self.scroll();
+    }
+
+    // A click moves the cursor, dragging selects text and the wheel scrolls without moving the cursor
+    fn process_mouse(&mut self, mouse: MouseEvent) {
+        if !matches!(self.mode, Mode::Normal | Mode::Insert | Mode::Visual) {
+            return;
+        }
+
+        match mouse.kind {
+            MouseKind::ScrollUp => {
+                self.row_offset = self.row_offset.saturating_sub(SCROLL_LINES);
+            }
+            MouseKind::ScrollDown => {
+                // The last line can be scrolled up to the top of the screen
+                self.row_offset = (self.row_offset + SCROLL_LINES).min(self.content.last_row());
+            }
+            MouseKind::Press(MouseButton::Left) => {
+                let Some(position) = self.position_at(mouse.column, mouse.row) else {
+                    return;
+                };
+                if self.mode == Mode::Visual {
+                    self.mode = Mode::Normal;
+                }
+                self.move_cursor_to(position);
+            }
+            MouseKind::Drag(MouseButton::Left) => {
+                // Dragging past the text area selects up to its edge
+                let y = mouse
+                    .row
+                    .min(self.screen.get_text_height().saturating_sub(1));
+                let Some(position) = self.position_at(mouse.column, y) else {
+                    return;
+                };
+                // The drag starts where the button was pressed, which is where the cursor is
+                if self.mode == Mode::Normal && position != (self.cursor_row, self.cursor_column) {
+                    self.enter_visual_mode();
+                }
+                self.move_cursor_to(position);
+            }
+            _ => {}
+        }
+    }
+
+    // The row and column drawn at the cell of the text area, None outside of it.
+    // Cells after the end of a line or below the last line map to the closest position.
+    fn position_at(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if y >= self.screen.get_text_height() {
+            return None;
+        }
+
+        let row = (self.row_offset + y).min(self.content.last_row());
+        // The column offset the screen was drawn with
+        let (column_offset, _) = self.get_horizontal_cursor_position(
+            self.cursor_row,
+            self.cursor_column,
+            self.column_offset,
+        );
+        let layout = LineLayout::new(&Cow::from(self.content.line(row)), self.options.tab_width);
+        let column = layout
+            .column_at(column_offset + x)
+            .min(self.last_column(row));
+        Some((row, column))
+    }
+
+    fn move_cursor_to(&mut self, (row, column): (usize, usize)) {
+        self.cursor_row = row;
+        self.cursor_column = column;
+        self.scroll();
+    }
+
+    fn start_search(&mut self, direction: Direction) {
+        self.mode = Mode::Search;
+        self.search_direction = direction;
+        self.search_line.start(match direction {
+            Direction::Forward => '/',
+            Direction::Backward => '?',
+        });
+        self.saved_position = (
+            self.cursor_row,
+            self.cursor_column,
+            self.row_offset,
+            self.column_offset,
+        );
+    }
+
+    fn process_search_key(&mut self, key: KeyEvent) {
+        let event = self.search_line.process_key(key);
+        self.process_search_event(event);
+    }
+
+    fn process_search_event(&mut self, event: CommandLineEvent) {
+        match event {
+            CommandLineEvent::Edited => {
+                // Incremental search, always starting from where the search was started.
+                // Patterns that are not valid (yet) while typing do not match anything.
+                self.restore_saved_position();
+                self.incremental_regex = self
+                    .build_search_regex(self.search_line.text(), None)
+                    .ok()
+                    .filter(|_| !self.search_line.text().is_empty());
+                if let Some(regex) = self.incremental_regex.clone() {
+                    self.search(&regex, self.search_direction, false);
+                }
+            }
+            CommandLineEvent::Submitted(pattern) => {
+                self.mode = Mode::Normal;
+                self.restore_saved_position();
+
+                // An empty pattern repeats the last search
+                let regex = match pattern.is_empty() {
+                    true => self.search_regex.clone(),
+                    false => match self.build_search_regex(&pattern, None) {
+                        Ok(regex) => Some(regex),
+                        Err(message) => return self.set_message(message),
+                    },
+                };
+                let Some(regex) = regex else {
+                    return self.set_message("E35: No previous regular expression".to_string());
+                };
+
+                self.search(&regex, self.search_direction, true);
+                self.search_regex = Some(regex);
+                self.highlight_search = true;
+            }
+            CommandLineEvent::Cancelled => {
+                self.mode = Mode::Normal;
+                self.restore_saved_position();
+            }
+            CommandLineEvent::None => {}
+        }
+    }
+
+    fn repeat_search(&mut self, reverse: bool) {
+        let Some(regex) = self.search_regex.clone() else {
+            self.set_message("E35: No previous regular expression".to_string());
+            return;
+        };
+
+        let direction = match reverse {
+            true => self.search_direction.reverse(),
+            false => self.search_direction,
+        };
+        self.highlight_search = true;
+        self.search(&regex, direction, true);
+    }
+
+    // Compiles a pattern using the case options, 'ignore_case' overrides them if given
+    fn build_search_regex(
+        &self,
+        pattern: &str,
+        ignore_case: Option<bool>,
+    ) -> Result<Regex, String> {
+        match ignore_case {
+            Some(ignore_case) => build_regex(pattern, ignore_case, false),
+            None => build_regex(pattern, self.options.ignore_case, self.options.smart_case),
+        }
+    }
+
+    // Moves the cursor to the next match, messages are only shown if 'report' is set
+    fn search(&mut self, regex: &Regex, direction: Direction, report: bool) {
+        let position = (self.cursor_row, self.cursor_column);
+        match search::find(&self.content, regex, position, direction) {
+            Some(found) => {
+                self.cursor_row = found.row;
+                self.cursor_column = found.column;
+                self.scroll();
+
+                if report && found.wrapped {
+                    self.set_message(
+                        match direction {
+                            Direction::Forward => "search hit BOTTOM, continuing at TOP",
+                            Direction::Backward => "search hit TOP, continuing at BOTTOM",
+                        }
+                        .to_string(),
                     );
                 }
             }
+            None if report => {
+                self.set_message(format!("E486: Pattern not found: {}", regex.as_str()))
+            }
+            None => {}
+        }
+    }
 
-            // Refresh screen to show the updated content
+    fn restore_saved_position(&mut self) {
+        (
+            self.cursor_row,
+            self.cursor_column,
+            self.row_offset,
+            self.column_offset,
+        ) = self.saved_position;
+    }
+
+    fn execute_command(&mut self, command: Command) -> Result<(), String> {
+        log!("Executing command: {}", self.command_line.text());
+
+        match command {
+            Command::Write {
+                filename,
+                force,
+                create_directories,
+            } => {
+                self.save(filename.as_deref(), force, create_directories);
+            }
+            Command::Quit { force } => {
+                if self.content.modified() && !force {
+                    return Err("E37: No write since last change (add ! to override)".to_string());
+                }
+                self.should_quit = true;
+            }
+            Command::WriteQuit { force } => {
+                self.should_quit = self.save(None, force, false);
+            }
+            Command::Edit { filename, force } => {
+                if self.content.modified() && !force {
+                    return Err("E37: No write since last change (add ! to override)".to_string());
+                }
+                self.filename = Some(filename);
+                self.editor_open_file();
+                self.cursor_row = 0;
+                self.cursor_column = 0;
+                self.row_offset = 0;
+                self.column_offset = 0;
+            }
+            Command::Set { option, value } => {
+                self.options.set(&option, value.as_deref())?;
+                self.screen.set_tab_width(self.options.tab_width);
+            }
+            Command::GoToLine(address) => {
+                self.cursor_row = self.resolve_address(address);
+                self.reset_cursor();
+                self.scroll();
+            }
+            Command::NoHighlight => self.highlight_search = false,
+            Command::Empty => {}
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                flags,
+            } => self.substitute(range, &pattern, &replacement, flags)?,
+        }
+        Ok(())
+    }
+
+    // Returns the row of an address, clamped to the content
+    fn resolve_address(&self, address: Address) -> usize {
+        let last_row = self.content.last_row();
+        match address {
+            // Lines are counted from 1, ':0' goes to the first line like ':1'
+            Address::Line(line) => line.saturating_sub(1).min(last_row),
+            Address::Current => self.cursor_row,
+            Address::Last => last_row,
+        }
+    }
+
+    fn substitute(
+        &mut self,
+        range: Option<Range>,
+        pattern: &str,
+        replacement: &str,
+        flags: SubstituteFlags,
+    ) -> Result<(), String> {
+        // An empty pattern uses the last searched pattern
+        let regex = match pattern.is_empty() {
+            true => match (&self.search_regex, flags.ignore_case) {
+                (Some(regex), None) => regex.clone(),
+                (Some(regex), ignore_case) => {
+                    self.build_search_regex(regex.as_str(), ignore_case)?
+                }
+                (None, _) => return Err("E35: No previous regular expression".to_string()),
+            },
+            false => self.build_search_regex(pattern, flags.ignore_case)?,
+        };
+
+        let (first_row, last_row) = match range {
+            Some(range) => (
+                self.resolve_address(range.start),
+                self.resolve_address(range.end),
+            ),
+            None => (self.cursor_row, self.cursor_row),
+        };
+        if first_row > last_row {
+            return Err("E493: Backwards range given".to_string());
+        }
+
+        // The substituted pattern becomes the last search pattern, like in vim
+        self.search_regex = Some(regex.clone());
+
+        // The whole substitution is undone as a single step
+        self.content
+            .begin_undo_group((self.cursor_row, self.cursor_column));
+
+        let mut substitution =
+            Substitution::new(regex, replacement, flags.global, first_row, last_row);
+        if flags.confirm {
+            self.saved_position = (
+                self.cursor_row,
+                self.cursor_column,
+                self.row_offset,
+                self.column_offset,
+            );
+            match substitution.next_match(&self.content) {
+                Some(found) => {
+                    self.mode = Mode::Confirm;
+                    self.show_match(found);
+                    self.substitution = Some((substitution, found));
+                }
+                None => self.finish_substitution(substitution),
+            }
+            return Ok(());
+        }
+
+        while let Some(found) = substitution.next_match(&self.content) {
+            substitution.replace(&mut self.content, found);
+        }
+        self.finish_substitution(substitution);
+        Ok(())
+    }
+
+    fn confirm_prompt(&self) -> String {
+        "replace with match? (y/n/a/q/l)".to_string()
+    }
+
+    fn process_confirm_key(&mut self, key: KeyEvent) {
+        let Some((mut substitution, found)) = self.substitution.take() else {
+            self.mode = Mode::Normal;
+            return;
+        };
+
+        // y: replace, n: skip, a: replace this and all remaining, l: replace this and stop
+        let next = match (key.code, key.modifiers) {
+            (KeyCode::Char('y'), Modifiers::NONE) => {
+                substitution.replace(&mut self.content, found);
+                true
+            }
+            (KeyCode::Char('n'), Modifiers::NONE) => {
+                substitution.skip(found);
+                true
+            }
+            (KeyCode::Char('a'), Modifiers::NONE) => {
+                substitution.replace(&mut self.content, found);
+                while let Some(found) = substitution.next_match(&self.content) {
+                    substitution.replace(&mut self.content, found);
+                }
+                false
+            }
+            (KeyCode::Char('l'), Modifiers::NONE) => {
+                substitution.replace(&mut self.content, found);
+                false
+            }
+            (KeyCode::Char('q') | KeyCode::Esc, Modifiers::NONE) => false,
+            _ => {
+                self.substitution = Some((substitution, found));
+                return;
+            }
+        };
+
+        match next
+            .then(|| substitution.next_match(&self.content))
+            .flatten()
+        {
+            Some(found) => {
+                self.show_match(found);
+                self.substitution = Some((substitution, found));
+            }
+            None => {
+                self.mode = Mode::Normal;
+                self.restore_saved_position();
+                self.finish_substitution(substitution);
+            }
+        }
+    }
+
+    fn show_match(&mut self, found: SubstituteMatch) {
+        self.cursor_row = found.row;
+        self.cursor_column = found.start;
+        self.scroll();
+    }
+
+    fn finish_substitution(&mut self, substitution: Substitution) {
+        if let Some(row) = substitution.last_replaced_row {
+            self.cursor_row = row.min(self.content.last_row());
+            self.cursor_column = 0;
+            self.scroll();
+        }
+        self.content
+            .end_undo_group((self.cursor_row, self.cursor_column));
+        self.highlight_search = true;
+
+        match substitution.replaced {
+            0 => self.set_message(format!(
+                "E486: Pattern not found: {}",
+                substitution.regex().as_str()
+            )),
+            replaced => self.set_message(format!(
+                "{} substitution{} on {} line{}",
+                replaced,
+                if replaced == 1 { "" } else { "s" },
+                substitution.replaced_rows,
+                if substitution.replaced_rows == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+            )),
+        }
+    }
+
+    fn enter_insert_mode(&mut self) {
+        log!("Entering insert mode");
+        // Text is inserted where the cursor is drawn, not at the column remembered from a longer line
+        self.reset_cursor();
+        self.mode = Mode::Insert;
+
+        // Everything typed until leaving insert mode is undone as a single step
+        self.content
+            .begin_undo_group((self.cursor_row, self.cursor_column));
+
+        // An empty buffer has no line to insert into yet
+        if self.content.line_count() == 0 {
+            self.content.insert_line(0, String::new());
+        }
+    }
+
+    fn undo(&mut self) {
+        match self.content.undo() {
+            Some(cursor) => self.restore_cursor(cursor),
+            None => log!("Already at oldest change"),
+        }
+    }
+
+    fn redo(&mut self) {
+        match self.content.redo() {
+            Some(cursor) => self.restore_cursor(cursor),
+            None => log!("Already at newest change"),
+        }
+    }
+
+    fn restore_cursor(&mut self, (row, column): (usize, usize)) {
+        self.cursor_row = row.min(self.content.last_row());
+        self.cursor_column = column;
+        self.reset_cursor();
+        self.scroll();
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.content
+            .insert_char(self.cursor_row, self.cursor_column, c);
+        self.cursor_column += 1;
+        self.scroll();
+    }
+
+    // Inserts the text at the cursor as a single undo step, without any indentation added
+    fn paste(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        // The text goes where the cursor is drawn, not at the column remembered from a longer line
+        self.reset_cursor();
+        self.content
+            .begin_undo_group((self.cursor_row, self.cursor_column));
+        if self.content.line_count() == 0 {
+            self.content.insert_line(0, String::new());
+        }
+        self.content.replace(
+            self.cursor_row,
+            self.cursor_column,
+            self.cursor_column,
+            text,
+        );
+
+        // The cursor moves to the end of the pasted text
+        let last_line = text.rsplit('\n').next().unwrap_or_default();
+        let line_breaks = text.matches('\n').count();
+        if line_breaks > 0 {
+            self.cursor_row += line_breaks;
+            self.cursor_column = 0;
+        }
+        self.cursor_column += last_line.chars().count();
+        self.content
+            .end_undo_group((self.cursor_row, self.cursor_column));
+
+        match self.mode {
+            // Typing after the paste is a new undo step
+            Mode::Insert => self
+                .content
+                .begin_undo_group((self.cursor_row, self.cursor_column)),
+            // In normal mode the cursor stays on the last pasted character
+            _ => {
+                self.cursor_column = grapheme::previous_boundary(
+                    self.content.line(self.cursor_row),
+                    self.cursor_column,
+                )
+            }
+        }
+        self.scroll();
+    }
+
+    fn insert_newline(&mut self) {
+        self.content.split_line(self.cursor_row, self.cursor_column);
+        self.cursor_row += 1;
+        self.cursor_column = 0;
+        self.scroll();
+    }
+
+    fn delete_char_before_cursor(&mut self) {
+        if self.cursor_column > 0 {
+            // Removes the whole grapheme cluster, e.g. a letter together with its accents
+            let end = self.cursor_column;
+            self.cursor_column =
+                grapheme::previous_boundary(self.content.line(self.cursor_row), end);
+            self.content
+                .replace(self.cursor_row, self.cursor_column, end, "");
+        } else if self.cursor_row > 0 {
+            // Backspace at the start of a line joins it with the previous one
+            self.cursor_row -= 1;
+            self.cursor_column = self.content.line_len(self.cursor_row);
+            self.content.join_lines(self.cursor_row);
+        }
+        self.scroll();
+    }
+
+    fn delete_char_at_cursor(&mut self) {
+        if self.cursor_column < self.content.line_len(self.cursor_row) {
+            let end =
+                grapheme::next_boundary(self.content.line(self.cursor_row), self.cursor_column);
+            self.content
+                .replace(self.cursor_row, self.cursor_column, end, "");
+        } else {
+            // Delete at the end of a line pulls the next line up
+            self.content.join_lines(self.cursor_row);
         }
     }
 
-    fn move_cursor(&mut self, key: Key) {
-        match key {
-            Key::ArrowUp | Key::Other(b'k') => self.move_cursor_up(),
-            Key::ArrowDown | Key::Other(b'j') => self.move_cursor_down(),
-            Key::ArrowRight | Key::Other(b'l') => self.move_cursor_right(),
-            Key::ArrowLeft | Key::Other(b'h') => self.move_cursor_left(),
-            Key::Other(b'^') => self.move_cursor_to_start_of_line(),
-            Key::Other(b'$') => self.move_cursor_to_end_of_line(),
+    // Adjusts the offsets so the cursor is inside the visible area
+    fn scroll(&mut self) {
+        if self.cursor_row < self.row_offset {
+            self.row_offset = self.cursor_row;
+        }
+        // A window without room for text still keeps the cursor row in view
+        let text_height = self.screen.get_text_height().max(1);
+        if self.cursor_row >= self.row_offset + text_height {
+            self.row_offset = self.cursor_row + 1 - text_height;
+        }
+        (self.column_offset, _) = self.get_horizontal_cursor_position(
+            self.cursor_row,
+            self.cursor_column,
+            self.column_offset,
+        );
+    }
+
+    // The last column the cursor can be on in the row,
+    // in insert mode the cursor can be placed after the last character
+    fn last_column(&self, row: usize) -> usize {
+        let line_len = self.content.line_len(row);
+        match self.mode {
+            _ if line_len == 0 => 0,
+            Mode::Normal | Mode::Command | Mode::Search | Mode::Confirm | Mode::Visual => {
+                grapheme::previous_boundary(self.content.line(row), line_len)
+            }
+            Mode::Insert => line_len,
+        }
+    }
+
+    fn move_cursor(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => self.move_cursor_up(),
+            KeyCode::Down | KeyCode::Char('j') => self.move_cursor_down(),
+            KeyCode::Right | KeyCode::Char('l') => self.move_cursor_right(),
+            KeyCode::Left | KeyCode::Char('h') => self.move_cursor_left(),
+            KeyCode::Home | KeyCode::Char('^') => self.move_cursor_to_start_of_line(),
+            KeyCode::End | KeyCode::Char('$') => self.move_cursor_to_end_of_line(),
+            KeyCode::PageUp => self.move_page_up(),
+            KeyCode::PageDown => self.move_page_down(),
             _ => {}
         }
+        self.scroll();
         trace!("Cursor: {}, {}", self.cursor_row, self.cursor_column);
         trace!("Offset: {}, {}", self.row_offset, self.column_offset);
     }
@@ -133,24 +1134,36 @@ impl Editor {
     }
 
     fn move_cursor_down(&mut self) {
-        if self.cursor_row < self.content.lines.len() - 1 {
+        if self.cursor_row < self.content.last_row() {
             self.cursor_row += 1;
 
-            if self.cursor_row > self.row_offset + self.screen.get_height() - 1 {
+            if self.cursor_row >= self.row_offset + self.screen.get_text_height() {
                 self.row_offset += 1;
             }
         }
     }
 
+    // Scrolls up by a screen, the cursor moves along
+    fn move_page_up(&mut self) {
+        let text_height = self.screen.get_text_height();
+        self.cursor_row = self.cursor_row.saturating_sub(text_height);
+        self.row_offset = self.row_offset.saturating_sub(text_height);
+    }
+
+    fn move_page_down(&mut self) {
+        let text_height = self.screen.get_text_height();
+        let last_row = self.content.last_row();
+        self.cursor_row = (self.cursor_row + text_height).min(last_row);
+        self.row_offset = (self.row_offset + text_height).min(last_row);
+    }
+
     fn move_cursor_left(&mut self) {
         self.reset_cursor();
 
         if self.cursor_column > 0 {
-            self.cursor_column -= 1;
-
-            if self.cursor_column < self.column_offset {
-                self.column_offset -= 1;
-            }
+            self.cursor_column =
+                grapheme::previous_boundary(self.content.line(self.cursor_row), self.cursor_column);
+            self.scroll();
         } else {
             if self.cursor_row > 0 {
                 self.move_cursor_up();
@@ -162,14 +1175,12 @@ impl Editor {
     fn move_cursor_right(&mut self) {
         self.reset_cursor();
 
-        if self.cursor_column < self.content.lines[self.cursor_row].len() - 1 {
-            self.cursor_column += 1;
-
-            if self.cursor_column > self.column_offset + self.screen.get_width() - 1 {
-                self.column_offset += 1;
-            }
+        if self.cursor_column < self.last_column(self.cursor_row) {
+            self.cursor_column =
+                grapheme::next_boundary(self.content.line(self.cursor_row), self.cursor_column);
+            self.scroll();
         } else {
-            if self.cursor_row < self.content.lines.len() - 1 {
+            if self.cursor_row < self.content.last_row() {
                 self.move_cursor_down();
                 self.move_cursor_to_start_of_line();
             }
@@ -182,19 +1193,14 @@ impl Editor {
     }
 
     fn move_cursor_to_end_of_line(&mut self) {
-        self.cursor_column = self.content.lines[self.cursor_row].len() - 1;
-
-        if self.cursor_column >= self.screen.get_width() {
-            self.column_offset = self.cursor_column - self.screen.get_width() + 1;
-        } else {
-            self.column_offset = 0;
-        }
+        self.cursor_column = self.last_column(self.cursor_row);
+        self.scroll();
     }
 
     fn reset_cursor(&mut self) {
         // Resetting the cursor position to a valid position
         let (new_column_offset, new_cursor_x) = self.get_horizontal_cursor_position(
-            &self.content.lines[self.cursor_row],
+            self.cursor_row,
             self.cursor_column,
             self.column_offset,
         );
@@ -202,39 +1208,589 @@ impl Editor {
         self.cursor_column = new_cursor_x;
     }
 
+    // Returns the column offset and the cursor column clamped to the row.
+    // A cursor inside a grapheme cluster is moved to its start,
+    // the offset is a render column that is moved so the cursor is visible.
     pub fn get_horizontal_cursor_position(
         &self,
-        content_line: &str,
+        row: usize,
         cursor_x: usize,
         column_offset: usize,
     ) -> (usize, usize) {
-        if cursor_x >= content_line.len() {
-            let new_cursor_x = content_line.len() - 1;
+        if row >= self.content.line_count() {
+            return (0, 0);
+        }
+        let (_, cursor_x) = self.clamp_position((row, cursor_x));
+        let line = self.content.line(row);
 
-            if new_cursor_x > column_offset {
-                (column_offset, new_cursor_x)
-            } else {
-                (new_cursor_x, new_cursor_x)
-            }
+        let layout = LineLayout::new(&Cow::from(line), self.options.tab_width);
+        let start = layout.render_column(cursor_x);
+        let end = start + layout.width_at(cursor_x);
+        let width = self.screen.get_width();
+        let column_offset = if start < column_offset {
+            start
+        } else if end > column_offset + width {
+            end.saturating_sub(width)
         } else {
-            (column_offset, cursor_x)
+            column_offset
+        };
+
+        (column_offset, cursor_x)
+    }
+
+    // Moves a column past the end of its row onto the last character, or after it in insert mode
+    fn clamp_position(&self, (row, column): (usize, usize)) -> (usize, usize) {
+        if row >= self.content.line_count() {
+            return (row.min(self.content.last_row()), 0);
         }
+        let line = self.content.line(row);
+        let column = grapheme::boundary_at_or_before(line, column).min(self.last_column(row));
+        (row, column)
     }
 
+    // Loads the file being edited. A file that does not exist yet is edited as an empty buffer
+    // that creates it when written, other errors are shown in the message bar.
     pub fn editor_open_file(&mut self) {
-        self.content.lines.clear();
-
-        let file = File::open(self.filename.clone());
-        if let Err(e) = file {
-            log!("Failed to open file: {}", e);
+        let Some(filename) = self.filename.clone() else {
             return;
+        };
+        self.content = match Content::from_file(&filename) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log!("Editing new file: {}", filename);
+                match missing_directory(&filename) {
+                    None => self.set_message(format!("\"{}\" [New]", filename)),
+                    Some(_) => self.set_message(format!("\"{}\" [New DIRECTORY]", filename)),
+                }
+                Content::new()
+            }
+            Err(e) => {
+                error!("Failed to open {}: {}", filename, e);
+                // The buffer is unnamed so saving it cannot replace the file it failed to load
+                self.filename = None;
+                self.set_message(format!(
+                    "Can't open \"{}\": {}",
+                    filename,
+                    describe_error(&e)
+                ));
+                Content::new()
+            }
+        };
+    }
+}
+
+// The directory of the file if it does not exist yet
+fn missing_directory(filename: &str) -> Option<&Path> {
+    Path::new(filename)
+        .parent()
+        .filter(|directory| !directory.as_os_str().is_empty() && !directory.exists())
+}
+
+// Why a file could not be read or written, for the message bar
+fn describe_error(e: &io::Error) -> String {
+    match e.kind() {
+        // Our own errors already explain themselves
+        ErrorKind::PermissionDenied if e.raw_os_error().is_some() => {
+            "permission denied".to_string()
         }
-        let reader = io::BufReader::new(file.unwrap());
-        let lines = reader.lines();
+        ErrorKind::IsADirectory => "is a directory".to_string(),
+        ErrorKind::InvalidData => "not valid UTF-8".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::script::ScriptedInput;
+
+    // Plays back the keys in an editor showing the text on a screen with 6 rows of text
+    fn run(text: &str, keys: &str) -> Editor<ScriptedInput, MemoryBackend> {
+        let screen = Screen::with_backend(MemoryBackend::new(20, 8)).unwrap();
+        let mut editor = Editor::with_io(ScriptedInput::new(keys), screen);
+        editor.content = Content::from_text(text);
+        editor.editor_open();
+        editor
+    }
+
+    fn cursor(text: &str, keys: &str) -> (usize, usize) {
+        let editor = run(text, keys);
+        (editor.cursor_row, editor.cursor_column)
+    }
+
+    fn text_after(text: &str, keys: &str) -> String {
+        run(text, keys).content.text().to_string()
+    }
+
+    // Where the cursor is drawn on the screen
+    fn screen_cursor(text: &str, keys: &str) -> Option<(usize, usize)> {
+        run(text, keys).screen.backend().cursor()
+    }
+
+    fn lines(count: usize) -> String {
+        (0..count).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn up_and_down() {
+        let text = "one\ntwo\nthree";
+        assert_eq!(cursor(text, "jj"), (2, 0));
+        assert_eq!(cursor(text, "<Down><Down><Up>"), (1, 0));
+        assert_eq!(cursor(text, "k"), (0, 0));
+        // The cursor stops at the last line
+        assert_eq!(cursor(text, "jjjj"), (2, 0));
+        assert_eq!(cursor(text, "jjjjk"), (1, 0));
+    }
+
+    #[test]
+    fn left_and_right() {
+        let text = "abc\ndef";
+        assert_eq!(cursor(text, "ll"), (0, 2));
+        assert_eq!(cursor(text, "llh"), (0, 1));
+        assert_eq!(cursor(text, "h"), (0, 0));
+        // Moving past the end of a line continues on the next one and the other way around
+        assert_eq!(cursor(text, "lll"), (1, 0));
+        assert_eq!(cursor(text, "jh"), (0, 2));
+        // The cursor stops at the last character
+        assert_eq!(cursor(text, "jllll"), (1, 2));
+    }
+
+    #[test]
+    fn start_and_end_of_line() {
+        let text = "abc\ndefgh";
+        assert_eq!(cursor(text, "$"), (0, 2));
+        assert_eq!(cursor(text, "j<End>"), (1, 4));
+        assert_eq!(cursor(text, "j$^"), (1, 0));
+        assert_eq!(cursor(text, "l<Home>"), (0, 0));
+        // Insert mode places the cursor after the last character
+        assert_eq!(cursor(text, "i<End>"), (0, 3));
+        assert_eq!(cursor(text, "i<End><Esc>"), (0, 2));
+    }
+
+    #[test]
+    fn empty_lines() {
+        let text = "abc\n\ndef";
+        assert_eq!(cursor(text, "j"), (1, 0));
+        assert_eq!(cursor(text, "j$"), (1, 0));
+        assert_eq!(cursor(text, "jl"), (2, 0));
+        assert_eq!(cursor(text, "jh"), (0, 2));
+        assert_eq!(cursor(text, "$jj"), (2, 2));
+        assert_eq!(cursor("\n\n", "jjjl"), (1, 0));
+    }
+
+    #[test]
+    fn column_is_clamped_to_shorter_lines() {
+        let text = "abcdef\nab\nabcdef";
+        assert_eq!(screen_cursor(text, "$j"), Some((1, 1)));
+        assert_eq!(cursor(text, "$jh"), (1, 0));
+        assert_eq!(cursor(text, "$jl"), (2, 0));
+        // Like in vim, the column is remembered while moving over shorter lines
+        assert_eq!(cursor(text, "$jj"), (2, 5));
+        assert_eq!(cursor(text, "$j<Down>"), (2, 5));
+    }
+
+    #[test]
+    fn wide_characters() {
+        let text = "日本語abc\na\u{301}e";
+        assert_eq!(cursor(text, "ll"), (0, 2));
+        assert_eq!(screen_cursor(text, "ll"), Some((4, 0)));
+        assert_eq!(cursor(text, "$"), (0, 5));
+        assert_eq!(screen_cursor(text, "$"), Some((8, 0)));
+        // A character with a combining accent is a single step
+        assert_eq!(cursor(text, "jl"), (1, 2));
+        assert_eq!(screen_cursor(text, "jl"), Some((1, 1)));
+    }
+
+    #[test]
+    fn tabs() {
+        // The cursor is drawn on the last cell of a tab
+        assert_eq!(screen_cursor("\tx", ""), Some((3, 0)));
+        assert_eq!(screen_cursor("\tx", "l"), Some((4, 0)));
+        assert_eq!(screen_cursor("a\tx", "l"), Some((3, 0)));
+    }
+
+    #[test]
+    fn long_lines_scroll_horizontally() {
+        let text = format!("{}b\nshort", "a".repeat(30));
+        let editor = run(&text, "$");
+        assert_eq!((editor.cursor_column, editor.column_offset), (30, 11));
+        assert_eq!(editor.screen.backend().cursor(), Some((19, 0)));
+        assert_eq!(
+            editor.screen.backend().rows()[0],
+            format!("{}b", "a".repeat(19))
+        );
+
+        // Moving to a shorter line scrolls back to its last character
+        let editor = run(&text, "$j");
+        assert_eq!(editor.screen.backend().rows()[1].trim_end(), "t");
+        assert_eq!(editor.screen.backend().cursor(), Some((0, 1)));
+
+        // Wide characters are scrolled in whole
+        let editor = run(&format!("{}x", "日".repeat(10)), "$");
+        assert_eq!((editor.cursor_column, editor.column_offset), (10, 1));
+        assert_eq!(
+            editor.screen.backend().rows()[0],
+            format!(" {}x", "日".repeat(9))
+        );
+    }
+
+    #[test]
+    fn scrolling() {
+        let text = lines(20);
+        let editor = run(&text, &"j".repeat(10));
+        assert_eq!((editor.cursor_row, editor.row_offset), (10, 5));
+        assert_eq!(editor.screen.backend().cursor(), Some((0, 5)));
+
+        let editor = run(&text, &format!("{}{}", "j".repeat(10), "k".repeat(8)));
+        assert_eq!((editor.cursor_row, editor.row_offset), (2, 2));
+    }
+
+    #[test]
+    fn pages() {
+        let text = lines(20);
+        let editor = run(&text, "<PageDown>");
+        assert_eq!((editor.cursor_row, editor.row_offset), (6, 6));
+        let editor = run(&text, "<PageDown><PageDown><PageDown><PageDown>");
+        assert_eq!((editor.cursor_row, editor.row_offset), (19, 19));
+        let editor = run(&text, "<PageDown><PageDown><PageUp>");
+        assert_eq!((editor.cursor_row, editor.row_offset), (6, 6));
+        let editor = run(&text, "j<PageUp>");
+        assert_eq!((editor.cursor_row, editor.row_offset), (0, 0));
+    }
+
+    #[test]
+    fn motions_in_empty_content() {
+        let keys = [
+            "j",
+            "k",
+            "h",
+            "l",
+            "$",
+            "^",
+            "<PageDown>",
+            "<PageUp>",
+            "<Home>",
+            "<End>",
+            "jjlkh",
+        ];
+        for keys in keys {
+            assert_eq!(cursor("", keys), (0, 0), "{}", keys);
+        }
+        let keys = [
+            "<Down>",
+            "<Up>",
+            "<Right>",
+            "<Left>",
+            "<End>",
+            "<Home>",
+            "<PageDown>",
+            "<PageUp>",
+        ];
+        for keys in keys {
+            assert_eq!(cursor("", &format!("i{}", keys)), (0, 0), "i{}", keys);
+        }
+        // The cursor is drawn on the welcome message
+        assert_eq!(screen_cursor("", "jl"), Some((0, 0)));
+    }
+
+    #[test]
+    fn motions_on_empty_lines() {
+        for keys in [
+            "l",
+            "h",
+            "$",
+            "^",
+            "<End>",
+            "<PageDown>",
+            "i<End>",
+            "i<Right>",
+        ] {
+            assert_eq!(cursor("\n", keys), (0, 0), "{}", keys);
+        }
+        assert_eq!(cursor("\n\n\n", "jj"), (2, 0));
+        assert_eq!(cursor("\n\n\n", "jjjl"), (2, 0));
+        assert_eq!(cursor("\n\n\n", "jjh"), (1, 0));
+        assert_eq!(cursor("\n\n\n", "<PageDown>"), (2, 0));
+    }
+
+    #[test]
+    fn editing_empty_content() {
+        let editor = run("", "jiab<Esc>");
+        assert_eq!(editor.content.line(0), "ab");
+        assert_eq!((editor.cursor_row, editor.cursor_column), (0, 1));
+
+        // In insert mode the cursor can be after the last character
+        assert_eq!(cursor("", "iab<End>"), (0, 2));
+        assert_eq!(cursor("", "o"), (1, 0));
+        assert_eq!(cursor("", "i<BS><Del><CR>"), (1, 0));
+
+        // Undoing everything leaves an empty line
+        let editor = run("", "iab<Esc>ujl$");
+        assert_eq!(editor.content.line(0), "");
+        assert_eq!((editor.cursor_row, editor.cursor_column), (0, 0));
+    }
+
+    #[test]
+    fn editing_empty_lines() {
+        // Typing on a blank line reached from the end of a longer line edits the blank line
+        let text = "abc\n\ndef";
+        assert_eq!(text_after(text, "$jiX<Esc>"), "abc\nX\ndef");
+        assert_eq!(text_after(text, "$jaX<Esc>"), "abc\nX\ndef");
+        assert_eq!(text_after(text, "$ji<BS><Esc>"), "abc\ndef");
+        assert_eq!(text_after(text, "$ji<CR>X<Esc>"), "abc\n\nX\ndef");
+        assert_eq!(text_after(text, "$ji<Del><Esc>"), "abc\ndef");
+        assert_eq!(text_after(text, "$joX<Esc>"), "abc\n\nX\ndef");
+        assert_eq!(cursor(text, "$jiX<Esc>"), (1, 0));
+    }
 
-        for line in lines {
-            let line = line.unwrap();
-            self.content.lines.push(line);
+    #[test]
+    fn mouse_in_empty_content() {
+        let mut editor = run("", "");
+        for kind in [
+            MouseKind::ScrollDown,
+            MouseKind::Press(MouseButton::Left),
+            MouseKind::Drag(MouseButton::Left),
+        ] {
+            editor.process_mouse(MouseEvent {
+                kind,
+                column: 5,
+                row: 3,
+                modifiers: Modifiers::NONE,
+            });
         }
+        assert_eq!((editor.cursor_row, editor.cursor_column), (0, 0));
+        assert_eq!(editor.row_offset, 0);
+    }
+
+    // A directory for the files of a test that is empty at the start of the test
+    fn test_directory(name: &str) -> String {
+        let directory =
+            std::env::temp_dir().join(format!("miv-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory.to_string_lossy().into_owned()
+    }
+
+    fn open(filename: &str, keys: &str) -> Editor<ScriptedInput, MemoryBackend> {
+        let screen = Screen::with_backend(MemoryBackend::new(20, 8)).unwrap();
+        let mut editor = Editor::with_io(ScriptedInput::new(keys), screen);
+        editor.editor_open_with_file(filename.to_string());
+        editor
+    }
+
+    fn message(editor: &Editor<ScriptedInput, MemoryBackend>) -> &str {
+        editor.message.as_ref().map_or("", |(message, _)| message)
+    }
+
+    #[test]
+    fn empty_command_line() {
+        let editor = run("a\nb", "j:<CR>");
+        assert_eq!(message(&editor), "");
+        assert_eq!((editor.cursor_row, editor.cursor_column), (1, 0));
+        assert!(matches!(editor.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn new_file() {
+        let directory = test_directory("new_file");
+        let filename = format!("{}/new.txt", directory);
+
+        let editor = open(&filename, "");
+        assert_eq!(message(&editor), format!("\"{}\" [New]", filename));
+        assert!(!Path::new(&filename).exists());
+
+        let editor = open(&filename, "ihello<Esc>:w<CR>");
+        assert!(!editor.content.modified());
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "hello\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn new_directory() {
+        let directory = test_directory("new_directory");
+        let filename = format!("{}/a/b/new.txt", directory);
+
+        let editor = open(&filename, "ihello<Esc>:w<CR>");
+        assert_eq!(
+            message(&editor),
+            format!(
+                "Can't write \"{}\": directory \"{}/a/b\" does not exist, :w ++p creates it",
+                filename, directory
+            )
+        );
+        assert!(editor.content.modified());
+
+        let editor = open(&filename, "ihello<Esc>:w ++p<CR>");
+        assert!(!editor.content.modified());
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "hello\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn open_directory() {
+        let directory = test_directory("open_directory");
+        let editor = open(&directory, "");
+        assert_eq!(
+            message(&editor),
+            format!("Can't open \"{}\": is a directory", directory)
+        );
+        assert_eq!(editor.content.line_count(), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn unnamed_buffer() {
+        let directory = test_directory("unnamed_buffer");
+        let filename = format!("{}/named.txt", directory);
+
+        let editor = run("", "ihello<Esc>:w<CR>");
+        assert_eq!(message(&editor), "E32: No file name");
+        assert!(editor.content.modified());
+
+        // A failed write leaves the buffer unnamed
+        let missing = format!("{}/missing/named.txt", directory);
+        let editor = run("", &format!("ihello<Esc>:w {}<CR>", missing));
+        assert_eq!(editor.filename, None);
+        assert!(editor.content.modified());
+
+        // Writing the buffer to a file names it
+        let editor = run("", &format!("ihello<Esc>:w {}<CR>", filename));
+        assert_eq!(editor.filename.as_deref(), Some(filename.as_str()));
+        assert!(!editor.content.modified());
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "hello\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn insert_after_moving_to_a_shorter_line() {
+        let text = "abcdef\nab\nxyz";
+        assert_eq!(text_after(text, "$jiX<Esc>"), "abcdef\naXb\nxyz");
+        assert_eq!(text_after(text, "$jaX<Esc>"), "abcdef\nabX\nxyz");
+        assert_eq!(text_after(text, "$ji<BS><Esc>"), "abcdef\nb\nxyz");
+        assert_eq!(text_after(text, "$ji<CR><Esc>"), "abcdef\na\nb\nxyz");
+        assert_eq!(text_after(text, "$ji<Del><Esc>"), "abcdef\na\nxyz");
+        // In insert mode the column is remembered while moving up and down too
+        assert_eq!(text_after(text, "i<End><Down>X<Esc>"), "abcdef\nabX\nxyz");
+        assert_eq!(cursor(text, "i<End><Down><Down>"), (2, 6));
+        assert_eq!(text_after("abcdef\nab", "$jiX<Esc>"), "abcdef\naXb");
+    }
+
+    #[test]
+    fn selection_on_shorter_lines() {
+        let text = "abcdef\nab\nxyz";
+        assert_eq!(text_after(text, "$vjd"), "abcde\nxyz");
+        assert_eq!(text_after(text, "$jvjd"), "abcdef\na");
+        assert_eq!(text_after(text, "$jvd"), "abcdef\na\nxyz");
+        // A selection ending on an empty line takes its line break
+        assert_eq!(text_after("abc\n\ndef", "$vjd"), "abdef");
+        assert_eq!(text_after("abc\n\ndef", "$jvd"), "abc\ndef");
+
+        // The highlight covers what is deleted
+        let editor = run(text, "$vj");
+        assert_eq!(editor.selection(), ((0, 5), (1, 1)));
+    }
+
+    #[test]
+    fn paste_on_shorter_line() {
+        let mut editor = run("abcdef\nab", "$j");
+        editor.process_paste("X\nY");
+        assert_eq!(editor.content.text().to_string(), "abcdef\naX\nYb");
+        assert_eq!((editor.cursor_row, editor.cursor_column), (2, 0));
+
+        let mut editor = run("abcdef\nab", "i<End><Down>");
+        editor.process_paste("XY");
+        assert_eq!(editor.content.text().to_string(), "abcdef\nabXY");
+    }
+
+    #[test]
+    fn file_that_cannot_be_loaded() {
+        let directory = test_directory("file_that_cannot_be_loaded");
+        let filename = format!("{}/latin1.txt", directory);
+        fs::write(&filename, b"caf\xe9 au lait\n").unwrap();
+
+        let editor = open(&filename, "");
+        assert_eq!(
+            message(&editor),
+            format!("Can't open \"{}\": not valid UTF-8", filename)
+        );
+
+        // Saving does not replace the file with the empty buffer
+        let editor = open(&filename, "<C-s>");
+        assert_eq!(message(&editor), "E32: No file name");
+        assert_eq!(fs::read(&filename).unwrap(), b"caf\xe9 au lait\n");
+        let editor = open(&filename, "ix<Esc>:w<CR>");
+        assert!(editor.content.modified());
+        assert_eq!(fs::read(&filename).unwrap(), b"caf\xe9 au lait\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn read_only_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = test_directory("read_only_file");
+        let filename = format!("{}/read-only.txt", directory);
+        fs::write(&filename, "abc\n").unwrap();
+        fs::set_permissions(&filename, fs::Permissions::from_mode(0o444)).unwrap();
+
+        let editor = open(&filename, "ix<Esc>:w<CR>");
+        assert_eq!(
+            message(&editor),
+            format!(
+                "Can't write \"{}\": file is read-only (add ! to override)",
+                filename
+            )
+        );
+        assert!(editor.content.modified());
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "abc\n");
+
+        // Forcing the write replaces the file and keeps it read-only
+        let editor = open(&filename, "ix<Esc>:w!<CR>");
+        assert!(!editor.content.modified());
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "xabc\n");
+        let mode = fs::metadata(&filename).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o444);
+
+        let editor = open(&filename, "iy<Esc>:wq<CR>");
+        assert!(!editor.should_quit);
+        let editor = open(&filename, "iy<Esc>:wq!<CR>");
+        assert!(editor.should_quit);
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "yxabc\n");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn undo_all_changes() {
+        let editor = run("a\n", "iX<Esc>u");
+        assert!(!editor.content.modified());
+        assert!(run("a\n", "iX<Esc>u<C-r>").content.modified());
+        assert!(run("a\n", "iX<Esc>iY<Esc>u").content.modified());
+        // A single Ctrl-Q quits, so the keys after it are never played
+        assert_eq!(text_after("a\n", "iX<Esc>u<C-q>iY<Esc>"), "a");
+
+        let editor = run("", "ix<CR>y<Esc>u");
+        assert!(!editor.content.modified());
+        assert_eq!(editor.content.line_count(), 0);
+        assert_eq!(run("", "ix<Esc>u<C-r>").content.line_count(), 1);
+    }
+
+    #[test]
+    fn undo_after_saving() {
+        let directory = test_directory("undo_after_saving");
+        let filename = format!("{}/saved.txt", directory);
+        fs::write(&filename, "abc\n").unwrap();
+
+        assert!(open(&filename, "ix<Esc>:w<CR>u").content.modified());
+        assert!(!open(&filename, "ix<Esc>:w<CR>u<C-r>").content.modified());
+        assert!(!open(&filename, "ix<Esc>iy<Esc>u:w<CR>u<C-r>")
+            .content
+            .modified());
+        // Writing a copy keeps the changes unsaved
+        let copy = format!("{}/copy.txt", directory);
+        assert!(open(&filename, &format!("ix<Esc>:w {}<CR>", copy))
+            .content
+            .modified());
+        fs::remove_dir_all(&directory).unwrap();
     }
 }
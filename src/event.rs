@@ -0,0 +1,111 @@
+use crate::input::{KeyEvent, MouseEvent};
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr};
+
+// Everything the editor loop waits for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Key(KeyEvent),
+    // Text pasted with bracketed paste, line breaks are '\n'
+    Paste(String),
+    Mouse(MouseEvent),
+    // The terminal was resized
+    Resize,
+    // The timeout given to the wait passed without anything else happening
+    Timeout,
+}
+
+// Where the editor loop gets its events from, e.g. the terminal or a script
+pub trait EventSource {
+    // Waits for the next event or until the timeout passed, None when there are no more events
+    fn read_event(&mut self, timeout: Option<Duration>) -> Option<Event>;
+}
+
+// The SIGWINCH handler writes into this pipe, so a resize wakes up a poll like a key press does
+static RESIZE_PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static RESIZE_PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+    let fd = RESIZE_PIPE_WRITE.load(Ordering::Relaxed);
+    // Only async signal safe calls are allowed here, a full pipe already has a resize pending
+    unsafe {
+        libc::write(fd, [0u8].as_ptr().cast(), 1);
+    }
+}
+
+// Creates the resize pipe and installs the SIGWINCH handler, returns the end to poll
+pub fn install_resize_handler() -> io::Result<RawFd> {
+    let read_fd = RESIZE_PIPE_READ.load(Ordering::Relaxed);
+    if read_fd >= 0 {
+        return Ok(read_fd);
+    }
+
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+    RESIZE_PIPE_READ.store(fds[0], Ordering::Relaxed);
+    RESIZE_PIPE_WRITE.store(fds[1], Ordering::Relaxed);
+
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_sigwinch as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGWINCH, &action, ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(fds[0])
+}
+
+// Empties the resize pipe, several resizes in a row are handled as one
+pub fn drain_resize_pipe(fd: RawFd) {
+    let mut buffer = [0u8; 64];
+    while unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) } > 0 {}
+}
+
+// Waits until one of the file descriptors can be read or the timeout passed,
+// returns whether each of them is readable. No timeout waits forever.
+pub fn poll_readable<const N: usize>(fds: [RawFd; N], timeout: Option<Duration>) -> [bool; N] {
+    let mut poll_fds = fds.map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                // Round up, so we never wake up right before the deadline and poll again
+                remaining
+                    .as_micros()
+                    .div_ceil(1000)
+                    .min(libc::c_int::MAX as u128) as libc::c_int
+            }
+            None => -1,
+        };
+
+        let result = unsafe { libc::poll(poll_fds.as_mut_ptr(), N as libc::nfds_t, timeout) };
+        match result {
+            // A signal, e.g. SIGWINCH, interrupted the poll before anything happened
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            ..=0 => return [false; N],
+            _ => {
+                return poll_fds.map(|poll_fd| {
+                    poll_fd.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0
+                })
+            }
+        }
+    }
+}
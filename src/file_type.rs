@@ -0,0 +1,32 @@
+use std::path::Path;
+
+// Maps file extensions to the name of the file type shown in the status bar
+const FILE_TYPES: &[(&[&str], &str)] = &[
+    (&["rs"], "rust"),
+    (&["c", "h"], "c"),
+    (&["cpp", "cc", "hpp"], "cpp"),
+    (&["py"], "python"),
+    (&["js", "mjs"], "javascript"),
+    (&["ts"], "typescript"),
+    (&["go"], "go"),
+    (&["sh", "bash"], "sh"),
+    (&["md"], "markdown"),
+    (&["toml"], "toml"),
+    (&["json"], "json"),
+    (&["yaml", "yml"], "yaml"),
+    (&["ini", "conf", "cfg"], "conf"),
+    (&["log"], "log"),
+    (&["txt"], "text"),
+];
+
+pub fn file_type(filename: &str) -> &'static str {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    FILE_TYPES
+        .iter()
+        .find(|(extensions, _)| extensions.contains(&extension))
+        .map_or("no ft", |(_, name)| name)
+}
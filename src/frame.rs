@@ -0,0 +1,132 @@
+use crate::backend::{Backend, Style};
+use crate::grapheme;
+use std::iter;
+use unicode_segmentation::UnicodeSegmentation;
+
+// A cell of the terminal. A wide character is stored in its first cell, the cells it covers are empty.
+#[derive(Clone, PartialEq)]
+struct Cell {
+    text: String,
+    style: Style,
+}
+
+impl Cell {
+    fn blank() -> Cell {
+        Cell {
+            text: " ".to_string(),
+            style: None,
+        }
+    }
+
+    fn is_blank(&self) -> bool {
+        self.text == " " && self.style.is_none()
+    }
+}
+
+// The cells of a whole screen. The previously drawn frame is kept,
+// so the next one only has to write the cells that changed.
+pub struct Frame {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            cells: vec![Cell::blank(); width * height],
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // Makes every cell blank, the cells keep their allocations for the next frame
+    pub fn clear(&mut self) {
+        for cell in &mut self.cells {
+            cell.text.clear();
+            cell.text.push(' ');
+            cell.style = None;
+        }
+    }
+
+    // Draws the text starting at the cell, anything past the right edge is cut off
+    pub fn put(&mut self, x: usize, y: usize, text: &str, style: Style) {
+        if y >= self.height {
+            return;
+        }
+
+        let mut x = x;
+        for grapheme in text.graphemes(true) {
+            let width = grapheme::width(grapheme);
+            // A cluster without a width is drawn together with the one before it
+            if width == 0 {
+                if x > 0 {
+                    self.cell(x - 1, y).text.push_str(grapheme);
+                }
+                continue;
+            }
+            if x + width > self.width {
+                break;
+            }
+
+            let texts = iter::once(grapheme).chain(iter::repeat(""));
+            for (i, text) in texts.take(width).enumerate() {
+                let cell = self.cell(x + i, y);
+                cell.text.clear();
+                cell.text.push_str(text);
+                cell.style = style;
+            }
+            x += width;
+        }
+    }
+
+    fn cell(&mut self, x: usize, y: usize) -> &mut Cell {
+        &mut self.cells[y * self.width + x]
+    }
+
+    fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    // Draws what turns the previous frame on the backend into this one.
+    // Rows that did not change are skipped, the others are written from the first
+    // to the last changed cell and blank cells at the end of a row are cleared at once.
+    pub fn draw_changes(&self, previous: &Frame, backend: &mut impl Backend) {
+        for y in 0..self.height {
+            let (row, previous_row) = (self.row(y), previous.row(y));
+            let changed = |x: &usize| row[*x] != previous_row[*x];
+            let Some(first) = (0..self.width).find(changed) else {
+                continue;
+            };
+            let last = (0..self.width).rfind(changed).unwrap_or(first);
+
+            // Start at the first cell of a wide character
+            let start = (0..=first).rfind(|x| !row[*x].text.is_empty()).unwrap_or(0);
+            let blank_from = row
+                .iter()
+                .rposition(|cell| !cell.is_blank())
+                .map_or(0, |x| x + 1);
+            let end = (last + 1).min(blank_from).max(start);
+
+            backend.move_cursor(start, y);
+            let mut style = None;
+            for cell in &row[start..end] {
+                if cell.style != style {
+                    backend.set_style(cell.style);
+                    style = cell.style;
+                }
+                backend.write(&cell.text);
+            }
+            if style.is_some() {
+                backend.set_style(None);
+            }
+            if last >= blank_from {
+                backend.clear_row();
+            }
+        }
+    }
+}
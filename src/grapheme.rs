@@ -0,0 +1,63 @@
+use ropey::RopeSlice;
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// The cursor always sits on the boundary of a grapheme cluster, so a character with combining marks
+// or an emoji made of several code points is moved over and deleted as a whole.
+// Columns are char indices into the line, like everywhere else.
+
+// Returns the grapheme clusters of the line with the column they start at
+pub fn graphemes(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut column = 0;
+    line.graphemes(true).map(move |grapheme| {
+        let start = column;
+        column += grapheme.chars().count();
+        (start, grapheme)
+    })
+}
+
+// Number of terminal cells the grapheme cluster takes up, e.g. 2 for most CJK characters
+pub fn width(grapheme: &str) -> usize {
+    grapheme.width()
+}
+
+// The start of the grapheme cluster after the one at the column, or the end of the line
+pub fn next_boundary(line: RopeSlice, column: usize) -> usize {
+    let len = line.len_chars();
+    let line: Cow<str> = line.into();
+    let next = graphemes(&line)
+        .map(|(start, _)| start)
+        .find(|start| *start > column);
+    next.unwrap_or(len)
+}
+
+// The start of the grapheme cluster before the one at the column
+pub fn previous_boundary(line: RopeSlice, column: usize) -> usize {
+    let line: Cow<str> = line.into();
+    let previous = graphemes(&line)
+        .map(|(start, _)| start)
+        .take_while(|start| *start < column)
+        .last();
+    previous.unwrap_or(0)
+}
+
+// Moves a column that points into the middle of a grapheme cluster to its start
+pub fn boundary_at_or_before(line: RopeSlice, column: usize) -> usize {
+    if column >= line.len_chars() {
+        return column;
+    }
+    previous_boundary(line, column + 1)
+}
+
+// Cuts the text to at most the given number of terminal cells
+pub fn truncate(text: &str, max_width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += width(grapheme);
+        if used > max_width {
+            return &text[..index];
+        }
+    }
+    text
+}
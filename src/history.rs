@@ -0,0 +1,257 @@
+// A single change to the content, text can span multiple lines separated by '\n'
+#[derive(Clone)]
+pub enum Edit {
+    Insert {
+        row: usize,
+        column: usize,
+        text: String,
+    },
+    Delete {
+        row: usize,
+        column: usize,
+        text: String,
+    },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::Insert { row, column, text } => Edit::Delete {
+                row: *row,
+                column: *column,
+                text: text.clone(),
+            },
+            Edit::Delete { row, column, text } => Edit::Insert {
+                row: *row,
+                column: *column,
+                text: text.clone(),
+            },
+        }
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        match self {
+            Edit::Insert { row, column, .. } | Edit::Delete { row, column, .. } => (*row, *column),
+        }
+    }
+}
+
+// A group of edits that is undone and redone as a whole
+#[derive(Clone)]
+pub struct UndoStep {
+    pub edits: Vec<Edit>,
+
+    // Cursor positions (row, column) before and after the edits were made
+    pub cursor_before: Option<(usize, usize)>,
+    pub cursor_after: Option<(usize, usize)>,
+
+    // Whether the content had no lines at all before the edits
+    pub was_empty: bool,
+
+    // The versions of the content before and after the edits, see History
+    version_before: usize,
+    version_after: usize,
+}
+
+impl UndoStep {
+    fn new(cursor_before: Option<(usize, usize)>, version: usize) -> UndoStep {
+        UndoStep {
+            edits: vec![],
+            cursor_before,
+            cursor_after: None,
+            was_empty: false,
+            version_before: version,
+            version_after: version,
+        }
+    }
+
+    // Where to put the cursor after undoing this step
+    pub fn undo_cursor(&self) -> (usize, usize) {
+        self.cursor_before
+            .unwrap_or_else(|| self.edits.first().map_or((0, 0), Edit::position))
+    }
+
+    // Where to put the cursor after redoing this step
+    pub fn redo_cursor(&self) -> (usize, usize) {
+        self.cursor_after
+            .unwrap_or_else(|| self.edits.last().map_or((0, 0), Edit::position))
+    }
+}
+
+#[derive(Clone)]
+pub struct History {
+    undo_stack: Vec<UndoStep>,
+    redo_stack: Vec<UndoStep>,
+
+    // Edits are collected here while a group is open, e.g. during an insert mode session
+    group: Option<UndoStep>,
+
+    // Every edit creates a new version of the content, undo and redo return to earlier ones.
+    // The content is modified while its version is not the one that was saved.
+    version: usize,
+    last_version: usize,
+    saved_version: usize,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo_stack: vec![],
+            redo_stack: vec![],
+            group: None,
+            version: 0,
+            last_version: 0,
+            saved_version: 0,
+        }
+    }
+
+    // Records an edit, was_empty is whether the content had no lines before it
+    pub fn record(&mut self, edit: Edit, was_empty: bool) {
+        self.redo_stack.clear();
+
+        let version_before = self.version;
+        self.last_version += 1;
+        self.version = self.last_version;
+
+        let step = match &mut self.group {
+            Some(group) => group,
+            None => {
+                // Edits outside of a group are their own undo step
+                self.undo_stack.push(UndoStep::new(None, version_before));
+                self.undo_stack.last_mut().unwrap()
+            }
+        };
+        step.edits.push(edit);
+        step.was_empty |= was_empty;
+        step.version_after = self.version;
+    }
+
+    pub fn begin_group(&mut self, cursor: (usize, usize)) {
+        self.end_group(cursor);
+        self.group = Some(UndoStep::new(Some(cursor), self.version));
+    }
+
+    pub fn end_group(&mut self, cursor: (usize, usize)) {
+        if let Some(mut group) = self.group.take() {
+            // Groups without any edits, e.g. entering and leaving insert mode, are not undoable
+            if !group.edits.is_empty() {
+                group.cursor_after = Some(cursor);
+                self.undo_stack.push(group);
+            }
+        }
+    }
+
+    pub fn pop_undo(&mut self) -> Option<UndoStep> {
+        let step = self.undo_stack.pop()?;
+        self.version = step.version_before;
+        self.redo_stack.push(step.clone());
+        Some(step)
+    }
+
+    pub fn pop_redo(&mut self) -> Option<UndoStep> {
+        let step = self.redo_stack.pop()?;
+        self.version = step.version_after;
+        self.undo_stack.push(step.clone());
+        Some(step)
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_version = self.version;
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.version != self.saved_version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(row: usize, column: usize, text: &str) -> Edit {
+        Edit::Insert {
+            row,
+            column,
+            text: text.to_string(),
+        }
+    }
+
+    fn positions(step: &UndoStep) -> Vec<(usize, usize)> {
+        step.edits.iter().map(Edit::position).collect()
+    }
+
+    #[test]
+    fn groups() {
+        let mut history = History::new();
+        history.begin_group((0, 0));
+        history.record(insert(0, 0, "a"), false);
+        history.record(insert(0, 1, "b"), false);
+        history.end_group((0, 2));
+        // Edits outside of a group are undone one by one
+        history.record(insert(1, 0, "c"), false);
+
+        let step = history.pop_undo().unwrap();
+        assert_eq!(positions(&step), [(1, 0)]);
+        assert_eq!((step.undo_cursor(), step.redo_cursor()), ((1, 0), (1, 0)));
+        let step = history.pop_undo().unwrap();
+        assert_eq!(positions(&step), [(0, 0), (0, 1)]);
+        assert_eq!((step.undo_cursor(), step.redo_cursor()), ((0, 0), (0, 2)));
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn empty_groups() {
+        let mut history = History::new();
+        history.begin_group((0, 0));
+        history.end_group((0, 0));
+        assert!(history.pop_undo().is_none());
+
+        // Beginning a group ends the open one
+        history.begin_group((0, 0));
+        history.record(insert(0, 0, "a"), false);
+        history.begin_group((0, 1));
+        history.record(insert(0, 1, "b"), false);
+        history.end_group((0, 2));
+        assert_eq!(positions(&history.pop_undo().unwrap()), [(0, 1)]);
+        assert_eq!(positions(&history.pop_undo().unwrap()), [(0, 0)]);
+    }
+
+    #[test]
+    fn redo() {
+        let mut history = History::new();
+        history.record(insert(0, 0, "a"), false);
+        history.record(insert(0, 1, "b"), false);
+        history.pop_undo();
+        history.pop_undo();
+        assert_eq!(positions(&history.pop_redo().unwrap()), [(0, 0)]);
+
+        // A new edit clears what could be redone
+        history.record(insert(0, 1, "c"), false);
+        assert!(history.pop_redo().is_none());
+        assert_eq!(positions(&history.pop_undo().unwrap()), [(0, 1)]);
+        assert_eq!(positions(&history.pop_undo().unwrap()), [(0, 0)]);
+    }
+
+    #[test]
+    fn modified() {
+        let mut history = History::new();
+        assert!(!history.is_modified());
+        history.record(insert(0, 0, "a"), true);
+        assert!(history.is_modified());
+        assert!(history.pop_undo().unwrap().was_empty);
+        assert!(!history.is_modified());
+
+        history.pop_redo();
+        history.mark_saved();
+        history.record(insert(0, 1, "b"), false);
+        history.pop_undo();
+        assert!(!history.is_modified());
+        history.pop_undo();
+        assert!(history.is_modified());
+
+        // The saved version cannot be reached again after a different edit
+        history.record(insert(0, 0, "c"), false);
+        history.pop_undo();
+        assert!(history.is_modified());
+    }
+}
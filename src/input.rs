@@ -1,30 +1,348 @@
-use crate::{stdin_raw_mode::StdinRawMode, trace};
-use std::io::{self, Read};
+use crate::constants::{ESCAPE_TIMEOUT, KEYBOARD_ENHANCEMENT};
+use crate::event::{self, Event, EventSource};
+use crate::{crash, stdin_raw_mode::StdinRawMode, trace, warn};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{mem, str};
 
-// TODO: This should be part of the input module
-pub enum Key {
-    None,
+// How long the terminal has to answer a query, e.g. for the cursor position
+const REPORT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How long to wait for the rest of a paste before taking what arrived as the whole paste
+const PASTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Ends the text of a bracketed paste
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCode {
+    // A printable character, Shift is already applied, e.g. 'A' instead of Shift-'a'
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
     Esc,
-    ArrowUp,
-    ArrowDown,
-    ArrowLeft,
-    ArrowRight,
-    Other(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    // F1 to F12
+    Function(u8),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: false,
+    };
+    pub const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        ..Modifiers::NONE
+    };
+    pub const CTRL: Modifiers = Modifiers {
+        ctrl: true,
+        ..Modifiers::NONE
+    };
+
+    // Decodes the modifier parameter of a CSI sequence, which is 1 plus a bitmask
+    fn from_parameter(parameter: u32) -> Modifiers {
+        let mask = parameter.saturating_sub(1);
+        Modifiers {
+            shift: mask & 1 != 0,
+            alt: mask & 2 != 0,
+            ctrl: mask & 4 != 0,
+        }
+    }
+}
+
+// A key press, e.g. Ctrl-x is the code Char('x') with the ctrl modifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    pub fn new(code: KeyCode) -> KeyEvent {
+        KeyEvent::with_modifiers(code, Modifiers::NONE)
+    }
+
+    pub fn with_modifiers(code: KeyCode, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { code, modifiers }
+    }
+
+    pub fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::with_modifiers(KeyCode::Char(c), Modifiers::CTRL)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    // The mouse moved while the button is held down
+    Drag(MouseButton),
+    ScrollUp,
+    ScrollDown,
+}
+
+// A mouse report, the column and row of the cell the mouse is on start at 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseKind,
+    pub column: usize,
+    pub row: usize,
+    pub modifiers: Modifiers,
+}
+
+// The keyboard protocol the terminal was switched to, so it can be switched back
+enum KeyboardMode {
+    Legacy,
+    // The Kitty progressive enhancement flags were pushed onto the terminal's stack
+    Kitty,
+    // xterm's modifyOtherKeys, with the previous value if the terminal reported it
+    ModifyOtherKeys(Option<u32>),
 }
 
 pub struct Input {
     // TODO: Maybe we can read from here instead of the stdin module
     _stdin: StdinRawMode,
+
+    // Becomes readable when the terminal is resized
+    resize_fd: RawFd,
 }
 
+// Kept here so the panic hook can restore the keyboard protocol too
+static KEYBOARD_MODE: Mutex<KeyboardMode> = Mutex::new(KeyboardMode::Legacy);
+
 impl Input {
-    pub fn new() -> Input {
-        Input {
-            _stdin: StdinRawMode::new().unwrap(),
+    pub fn new() -> io::Result<Input> {
+        let input = Input {
+            _stdin: StdinRawMode::new()?,
+            resize_fd: event::install_resize_handler()?,
+        };
+        if *KEYBOARD_ENHANCEMENT {
+            let mode = input.enable_keyboard_enhancement()?;
+            *KEYBOARD_MODE.lock().unwrap_or_else(|e| e.into_inner()) = mode;
+        }
+        Ok(input)
+    }
+
+    // Asks the terminal for the Kitty keyboard protocol and its modifyOtherKeys value,
+    // then enables the Kitty protocol or falls back to modifyOtherKeys.
+    // Every terminal answers the device attributes query, its answer ends the replies.
+    fn enable_keyboard_enhancement(&self) -> io::Result<KeyboardMode> {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?u\x1b[?4m\x1b[c")?;
+        stdout.flush()?;
+
+        let mut kitty = false;
+        let mut modify_other_keys = None;
+        loop {
+            match self.read_reply() {
+                // The terminal does not answer, so we do not know what it supports
+                None => return Ok(KeyboardMode::Legacy),
+                Some((parameters, b'u')) if parameters.starts_with('?') => kitty = true,
+                Some((parameters, b'm')) if parameters.starts_with(">4") => {
+                    modify_other_keys = parameters
+                        .strip_prefix(">4;")
+                        .and_then(|value| value.parse().ok());
+                }
+                Some((parameters, b'c')) if parameters.starts_with('?') => break,
+                _ => {}
+            }
+        }
+
+        let mode = if kitty {
+            // Only disambiguate escape codes, everything else is still sent as text
+            stdout.write_all(b"\x1b[>1u")?;
+            KeyboardMode::Kitty
+        } else {
+            stdout.write_all(b"\x1b[>4;2m")?;
+            KeyboardMode::ModifyOtherKeys(modify_other_keys)
+        };
+        stdout.flush()?;
+        Ok(mode)
+    }
+
+    // Reads the answer to a query, 'ESC [ parameters final', and returns the parameters and final byte.
+    // Returns None if the terminal does not answer.
+    fn read_reply(&self) -> Option<(String, u8)> {
+        // Skip anything before the answer, e.g. keys pressed in the meantime
+        let mut previous = 0;
+        loop {
+            let c = read_byte(REPORT_TIMEOUT).ok()??;
+            if previous == b'\x1b' && c == b'[' {
+                break;
+            }
+            previous = c;
+        }
+
+        let mut parameters = String::new();
+        loop {
+            match read_byte(REPORT_TIMEOUT).ok()?? {
+                c @ 0x40..=0x7e => return Some((parameters, c)),
+                c => parameters.push(c as char),
+            }
+        }
+    }
+
+    // Fails when the input ended, a key that was read before that is returned first
+    fn read_key(&self) -> io::Result<Option<Event>> {
+        let mut error = None;
+        let event = Decoder::new(|timeout| match read_byte(timeout) {
+            Ok(c) => c.unwrap_or(0),
+            Err(e) => {
+                error.get_or_insert(e);
+                0
+            }
+        })
+        .read_event();
+        match (event, error) {
+            (None, Some(e)) => Err(e),
+            (event, _) => Ok(event),
+        }
+    }
+}
+
+impl EventSource for Input {
+    // Waits for the next key press, paste or resize, or until the timeout passed.
+    // Nothing is read while waiting, so an idle editor does not use any CPU.
+    fn read_event(&mut self, timeout: Option<Duration>) -> Option<Event> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let [key_pressed, resized] =
+                event::poll_readable([io::stdin().as_raw_fd(), self.resize_fd], remaining);
+
+            if resized {
+                event::drain_resize_pipe(self.resize_fd);
+                return Some(Event::Resize);
+            }
+            if key_pressed {
+                match self.read_key() {
+                    Ok(Some(event)) => return Some(event),
+                    // Unknown escape sequences are dropped and we keep waiting
+                    Ok(None) => {}
+                    // The terminal is gone, e.g. its window was closed, so there are no more events
+                    Err(e) => {
+                        warn!("Failed to read from stdin: {}", e);
+                        crash::recover_unsaved_buffer();
+                        return None;
+                    }
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Some(Event::Timeout);
+            }
         }
     }
+}
+
+// Reads a Cursor Position Report, the answer to 'ESC [ 6 n', and returns the row and column.
+// Returns None if the terminal does not answer. Raw mode has to be enabled, see Input.
+pub fn read_cursor_position() -> Option<(usize, usize)> {
+    let mut report = vec![];
+    loop {
+        match read_byte(REPORT_TIMEOUT).ok()?? {
+            b'R' => break,
+            c => report.push(c),
+        }
+    }
+
+    let report = str::from_utf8(&report).ok()?.strip_prefix("\x1b[")?;
+    let (row, column) = report.split_once(';')?;
+    Some((row.parse().ok()?, column.parse().ok()?))
+}
+
+// Reads a byte if one arrives before the timeout, fails at the end of the input
+fn read_byte(timeout: Duration) -> io::Result<Option<u8>> {
+    let [readable] = event::poll_readable([io::stdin().as_raw_fd()], Some(timeout));
+    if !readable {
+        return Ok(None);
+    }
+
+    // Read the file descriptor directly, the buffer of io::stdin would hide pending bytes from poll
+    let mut buffer = [0u8; 1];
+    let result = unsafe { libc::read(io::stdin().as_raw_fd(), buffer.as_mut_ptr().cast(), 1) };
+    match result {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(None),
+            e => Err(e),
+        },
+        0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of file")),
+        _ => Ok(Some(buffer[0])),
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        restore_keyboard_mode();
+    }
+}
+
+// Switches the terminal back to the keyboard protocol it used before, only the first call does anything
+pub fn restore_keyboard_mode() {
+    let mode = mem::replace(
+        &mut *KEYBOARD_MODE.lock().unwrap_or_else(|e| e.into_inner()),
+        KeyboardMode::Legacy,
+    );
+    let restore = match mode {
+        KeyboardMode::Legacy => return,
+        KeyboardMode::Kitty => "\x1b[<u".to_string(),
+        KeyboardMode::ModifyOtherKeys(Some(value)) => format!("\x1b[>4;{}m", value),
+        // Resets modifyOtherKeys to the terminal's default
+        KeyboardMode::ModifyOtherKeys(None) => "\x1b[>4m".to_string(),
+    };
+
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(restore.as_bytes());
+    let _ = stdout.flush();
+}
+
+// Turns the bytes sent by the terminal into keys and pastes,
+// 'read_byte' returns 0 when no byte arrived within the timeout it is given.
+// Returns None when nothing was read or the sequence is not known.
+struct Decoder<F: FnMut(Duration) -> u8> {
+    read_byte: F,
+}
+
+impl<F: FnMut(Duration) -> u8> Decoder<F> {
+    fn new(read_byte: F) -> Decoder<F> {
+        Decoder { read_byte }
+    }
+
+    // The rest of an escape sequence arrives right after the ESC,
+    // if it does not arrive in time the Esc key was pressed
+    fn read_byte(&mut self) -> u8 {
+        (self.read_byte)(*ESCAPE_TIMEOUT)
+    }
 
-    pub fn read_key(&self) -> Key {
+    fn read_event(&mut self) -> Option<Event> {
         let c = self.read_byte();
         if c != 0 {
             trace!("Read key: {}", c);
@@ -32,41 +350,519 @@ impl Input {
 
         match c {
             b'\x1b' => self.read_escape(),
-            0 => Key::None,
-            _ => Key::Other(c),
+            0 => None,
+            _ => self.decode_byte(c).map(Event::Key),
         }
     }
 
-    fn read_byte(&self) -> u8 {
-        let mut buffer = [0; 1];
-        io::stdin().read(&mut buffer).unwrap();
-        buffer[0]
+    // Decodes a key that is sent as a single byte or as UTF-8
+    fn decode_byte(&mut self, c: u8) -> Option<KeyEvent> {
+        let key = match c {
+            b'\r' => KeyEvent::new(KeyCode::Enter),
+            b'\t' => KeyEvent::new(KeyCode::Tab),
+            // Some terminals send ^H instead of DEL for Backspace
+            0x08 | 0x7f => KeyEvent::new(KeyCode::Backspace),
+            b'\x1b' => KeyEvent::new(KeyCode::Esc),
+            // Ctrl clears the upper bits of a letter, e.g. Ctrl-Q is 0x11
+            0x01..=0x1a => KeyEvent::ctrl((c | 0x60) as char),
+            0x1c..=0x1f => KeyEvent::ctrl((c | 0x40) as char),
+            0x80.. => KeyEvent::new(KeyCode::Char(self.read_utf8(c)?)),
+            _ => KeyEvent::new(KeyCode::Char(c as char)),
+        };
+        Some(key)
     }
 
-    fn read_escape(&self) -> Key {
+    // Reads the continuation bytes of a character, invalid sequences are dropped
+    fn read_utf8(&mut self, first: u8) -> Option<char> {
+        let len = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return None,
+        };
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            bytes.push(self.read_byte());
+        }
+
+        str::from_utf8(&bytes).ok()?.chars().next()
+    }
+
+    fn read_escape(&mut self) -> Option<Event> {
         let c = self.read_byte();
         trace!("Read escape sequence: {}", c);
 
-        match c {
-            b'[' => self.read_arrow(),
+        let key = match c {
+            b'[' => return self.read_csi(),
+            b'O' => self.read_ss3()?,
             // If we read 0 bytes, we have pressed esc
-            0 => Key::Esc,
-            // If the key is not part of an escape sequence, we return None
-            // TODO: Not sure if this is the best way to handle this
-            _ => Key::None,
+            0 => KeyEvent::new(KeyCode::Esc),
+            // Terminals send Alt-x as ESC followed by x
+            _ => {
+                let key = self.decode_byte(c)?;
+                let modifiers = Modifiers {
+                    alt: true,
+                    ..key.modifiers
+                };
+                KeyEvent::with_modifiers(key.code, modifiers)
+            }
+        };
+        Some(Event::Key(key))
+    }
+
+    // 'ESC [ parameters final', e.g. 'ESC [ A' for up, 'ESC [ 5 ~' for page up
+    // or 'ESC [ 1 ; 5 C' for Ctrl-Right
+    fn read_csi(&mut self) -> Option<Event> {
+        let mut parameters = vec![];
+        let final_byte = loop {
+            match self.read_byte() {
+                // The sequence was cut off
+                0 => return None,
+                c @ 0x20..=0x3f => parameters.push(c),
+                c => break c,
+            }
+        };
+        trace!("Read CSI sequence: {:?} {}", parameters, final_byte);
+
+        if let Some(parameters) = parameters.strip_prefix(b"<") {
+            return mouse_event(parameters, final_byte).map(Event::Mouse);
+        }
+        if final_byte == b'~' && parameters == b"200" {
+            return self.read_paste();
         }
+        csi_key(&parameters, final_byte).map(Event::Key)
     }
 
-    fn read_arrow(&self) -> Key {
+    // The text of a bracketed paste, up to 'ESC [ 201 ~'
+    fn read_paste(&mut self) -> Option<Event> {
+        let mut text = vec![];
+        while !text.ends_with(PASTE_END) {
+            match (self.read_byte)(PASTE_TIMEOUT) {
+                // The end of the paste never arrived, keep what we have
+                0 => break,
+                c => text.push(c),
+            }
+        }
+        let text = text.strip_suffix(PASTE_END).unwrap_or(&text);
+        trace!("Read paste of {} bytes", text.len());
+
+        // Terminals send line breaks as '\r'
+        let text = String::from_utf8_lossy(text)
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        Some(Event::Paste(text))
+    }
+
+    // 'ESC O final', sent for arrows, Home, End and F1 to F4 in application mode
+    fn read_ss3(&mut self) -> Option<KeyEvent> {
         let c = self.read_byte();
-        trace!("Read arrow sequence: {}", c);
+        trace!("Read SS3 sequence: {}", c);
+        Some(KeyEvent::new(final_key(c)?))
+    }
+}
 
-        match c {
-            b'A' => Key::ArrowUp,
-            b'B' => Key::ArrowDown,
-            b'C' => Key::ArrowRight,
-            b'D' => Key::ArrowLeft,
-            _ => Key::None,
+// Keys that are identified by the last byte of a CSI or SS3 sequence
+fn final_key(c: u8) -> Option<KeyCode> {
+    match c {
+        b'A' => Some(KeyCode::Up),
+        b'B' => Some(KeyCode::Down),
+        b'C' => Some(KeyCode::Right),
+        b'D' => Some(KeyCode::Left),
+        b'H' => Some(KeyCode::Home),
+        b'F' => Some(KeyCode::End),
+        b'P'..=b'S' => Some(KeyCode::Function(c - b'P' + 1)),
+        _ => None,
+    }
+}
+
+// The key of a CSI sequence, given its parameter bytes and final byte
+fn csi_key(parameters: &[u8], final_byte: u8) -> Option<KeyEvent> {
+    // Private sequences, e.g. late answers to queries, are not keys
+    if parameters.iter().any(|c| matches!(c, b'<'..=b'?')) {
+        return None;
+    }
+
+    // Sub-parameters after ':', e.g. the event type of the Kitty protocol, are ignored
+    let parameters: Vec<u32> = str::from_utf8(parameters)
+        .unwrap_or_default()
+        .split(';')
+        .map(|parameter| {
+            let parameter = parameter.split(':').next().unwrap_or_default();
+            parameter.parse().unwrap_or(0)
+        })
+        .collect();
+    let modifiers = Modifiers::from_parameter(parameters.get(1).copied().unwrap_or(1));
+
+    let code = match final_byte {
+        // modifyOtherKeys, 'ESC [ 27 ; modifiers ; code ~'
+        b'~' if parameters[0] == 27 => {
+            return code_point_key(parameters.get(2).copied()?, modifiers)
+        }
+        b'~' => match parameters[0] {
+            1 | 7 => KeyCode::Home,
+            2 => KeyCode::Insert,
+            3 => KeyCode::Delete,
+            4 | 8 => KeyCode::End,
+            5 => KeyCode::PageUp,
+            6 => KeyCode::PageDown,
+            11..=15 => KeyCode::Function((parameters[0] - 10) as u8),
+            17..=21 => KeyCode::Function((parameters[0] - 11) as u8),
+            23 | 24 => KeyCode::Function((parameters[0] - 12) as u8),
+            _ => return None,
+        },
+        // The Kitty protocol, 'ESC [ code ; modifiers u'
+        b'u' => return code_point_key(parameters[0], modifiers),
+        b'Z' => return Some(KeyEvent::with_modifiers(KeyCode::Tab, Modifiers::SHIFT)),
+        c => final_key(c)?,
+    };
+
+    Some(KeyEvent::with_modifiers(code, modifiers))
+}
+
+// An SGR mouse report, 'ESC [ < button ; column ; row M', the final byte is 'm' for a release.
+// The button number also holds the modifiers, whether the mouse moved and whether it is the wheel.
+fn mouse_event(parameters: &[u8], final_byte: u8) -> Option<MouseEvent> {
+    let parameters: Vec<usize> = str::from_utf8(parameters)
+        .ok()?
+        .split(';')
+        .map(|parameter| parameter.parse().ok())
+        .collect::<Option<_>>()?;
+    let [button, column, row] = parameters[..] else {
+        return None;
+    };
+
+    let kind = if button & 64 != 0 {
+        match button & 3 {
+            0 => MouseKind::ScrollUp,
+            1 => MouseKind::ScrollDown,
+            // Horizontal scrolling
+            _ => return None,
         }
+    } else {
+        let mouse_button = match button & 3 {
+            0 => MouseButton::Left,
+            1 => MouseButton::Middle,
+            2 => MouseButton::Right,
+            // Moving the mouse without a button held down
+            _ => return None,
+        };
+        match final_byte {
+            b'm' => MouseKind::Release(mouse_button),
+            b'M' if button & 32 != 0 => MouseKind::Drag(mouse_button),
+            b'M' => MouseKind::Press(mouse_button),
+            _ => return None,
+        }
+    };
+
+    Some(MouseEvent {
+        kind,
+        column: column.saturating_sub(1),
+        row: row.saturating_sub(1),
+        modifiers: Modifiers {
+            shift: button & 4 != 0,
+            alt: button & 8 != 0,
+            ctrl: button & 16 != 0,
+        },
+    })
+}
+
+// A key sent as its Unicode code point by the Kitty protocol or modifyOtherKeys
+pub fn code_point_key(code: u32, modifiers: Modifiers) -> Option<KeyEvent> {
+    let code = match code {
+        9 => KeyCode::Tab,
+        13 => KeyCode::Enter,
+        27 => KeyCode::Esc,
+        8 | 127 => KeyCode::Backspace,
+        // Keypad, media and modifier keys are in the private use area, we do not support them
+        57344..=63743 => return None,
+        _ => {
+            let c = char::from_u32(code).filter(|c| !c.is_control())?;
+            if !modifiers.shift {
+                return Some(KeyEvent::with_modifiers(KeyCode::Char(c), modifiers));
+            }
+
+            // Apply Shift to the character, like for keys that are sent as text
+            let mut upper = c.to_uppercase();
+            let c = match (upper.next(), upper.next()) {
+                (Some(upper), None) => upper,
+                _ => c,
+            };
+            let modifiers = Modifiers {
+                shift: false,
+                ..modifiers
+            };
+            return Some(KeyEvent::with_modifiers(KeyCode::Char(c), modifiers));
+        }
+    };
+    Some(KeyEvent::with_modifiers(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_event(bytes: &[u8]) -> Option<Event> {
+        let mut bytes = bytes.iter().copied();
+        Decoder::new(|_| bytes.next().unwrap_or(0)).read_event()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<KeyEvent> {
+        match decode_event(bytes)? {
+            Event::Key(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    fn key(code: KeyCode) -> Option<KeyEvent> {
+        Some(KeyEvent::new(code))
+    }
+
+    fn modified(code: KeyCode, shift: bool, alt: bool, ctrl: bool) -> Option<KeyEvent> {
+        Some(KeyEvent::with_modifiers(
+            code,
+            Modifiers { shift, alt, ctrl },
+        ))
+    }
+
+    #[test]
+    fn plain_bytes() {
+        assert_eq!(decode(b""), None);
+        assert_eq!(decode(b"a"), key(KeyCode::Char('a')));
+        assert_eq!(decode(b"A"), key(KeyCode::Char('A')));
+        assert_eq!(decode(b" "), key(KeyCode::Char(' ')));
+        assert_eq!(decode("ä".as_bytes()), key(KeyCode::Char('ä')));
+        assert_eq!(decode(b"\r"), key(KeyCode::Enter));
+        assert_eq!(decode(b"\t"), key(KeyCode::Tab));
+        assert_eq!(decode(b"\x7f"), key(KeyCode::Backspace));
+        assert_eq!(decode(b"\x08"), key(KeyCode::Backspace));
+        assert_eq!(decode(b"\x1b"), key(KeyCode::Esc));
+    }
+
+    #[test]
+    fn ctrl_keys() {
+        assert_eq!(decode(b"\x11"), Some(KeyEvent::ctrl('q')));
+        assert_eq!(decode(b"\x01"), Some(KeyEvent::ctrl('a')));
+        assert_eq!(decode(b"\x1a"), Some(KeyEvent::ctrl('z')));
+        assert_eq!(decode(b"\x1d"), Some(KeyEvent::ctrl(']')));
+    }
+
+    #[test]
+    fn alt_keys() {
+        assert_eq!(
+            decode(b"\x1bx"),
+            modified(KeyCode::Char('x'), false, true, false)
+        );
+        assert_eq!(
+            decode(b"\x1bX"),
+            modified(KeyCode::Char('X'), false, true, false)
+        );
+        assert_eq!(
+            decode(b"\x1b\x11"),
+            modified(KeyCode::Char('q'), false, true, true)
+        );
+        assert_eq!(
+            decode(b"\x1b\r"),
+            modified(KeyCode::Enter, false, true, false)
+        );
+        assert_eq!(
+            decode("\x1bä".as_bytes()),
+            modified(KeyCode::Char('ä'), false, true, false)
+        );
+    }
+
+    #[test]
+    fn arrows() {
+        assert_eq!(decode(b"\x1b[A"), key(KeyCode::Up));
+        assert_eq!(decode(b"\x1b[B"), key(KeyCode::Down));
+        assert_eq!(decode(b"\x1b[C"), key(KeyCode::Right));
+        assert_eq!(decode(b"\x1b[D"), key(KeyCode::Left));
+        assert_eq!(decode(b"\x1bOA"), key(KeyCode::Up));
+        assert_eq!(decode(b"\x1bOB"), key(KeyCode::Down));
+        assert_eq!(decode(b"\x1bOC"), key(KeyCode::Right));
+        assert_eq!(decode(b"\x1bOD"), key(KeyCode::Left));
+    }
+
+    #[test]
+    fn home_and_end() {
+        assert_eq!(decode(b"\x1b[H"), key(KeyCode::Home));
+        assert_eq!(decode(b"\x1b[F"), key(KeyCode::End));
+        assert_eq!(decode(b"\x1bOH"), key(KeyCode::Home));
+        assert_eq!(decode(b"\x1bOF"), key(KeyCode::End));
+        assert_eq!(decode(b"\x1b[1~"), key(KeyCode::Home));
+        assert_eq!(decode(b"\x1b[7~"), key(KeyCode::Home));
+        assert_eq!(decode(b"\x1b[4~"), key(KeyCode::End));
+        assert_eq!(decode(b"\x1b[8~"), key(KeyCode::End));
+    }
+
+    #[test]
+    fn editing_keys() {
+        assert_eq!(decode(b"\x1b[2~"), key(KeyCode::Insert));
+        assert_eq!(decode(b"\x1b[3~"), key(KeyCode::Delete));
+        assert_eq!(decode(b"\x1b[5~"), key(KeyCode::PageUp));
+        assert_eq!(decode(b"\x1b[6~"), key(KeyCode::PageDown));
+    }
+
+    #[test]
+    fn function_keys() {
+        assert_eq!(decode(b"\x1bOP"), key(KeyCode::Function(1)));
+        assert_eq!(decode(b"\x1bOQ"), key(KeyCode::Function(2)));
+        assert_eq!(decode(b"\x1bOR"), key(KeyCode::Function(3)));
+        assert_eq!(decode(b"\x1bOS"), key(KeyCode::Function(4)));
+        assert_eq!(decode(b"\x1b[11~"), key(KeyCode::Function(1)));
+        assert_eq!(decode(b"\x1b[15~"), key(KeyCode::Function(5)));
+        assert_eq!(decode(b"\x1b[17~"), key(KeyCode::Function(6)));
+        assert_eq!(decode(b"\x1b[21~"), key(KeyCode::Function(10)));
+        assert_eq!(decode(b"\x1b[23~"), key(KeyCode::Function(11)));
+        assert_eq!(decode(b"\x1b[24~"), key(KeyCode::Function(12)));
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(
+            decode(b"\x1b[1;2A"),
+            modified(KeyCode::Up, true, false, false)
+        );
+        assert_eq!(
+            decode(b"\x1b[1;3D"),
+            modified(KeyCode::Left, false, true, false)
+        );
+        assert_eq!(
+            decode(b"\x1b[1;5C"),
+            modified(KeyCode::Right, false, false, true)
+        );
+        assert_eq!(
+            decode(b"\x1b[1;8H"),
+            modified(KeyCode::Home, true, true, true)
+        );
+        assert_eq!(
+            decode(b"\x1b[3;5~"),
+            modified(KeyCode::Delete, false, false, true)
+        );
+        assert_eq!(
+            decode(b"\x1b[1;2P"),
+            modified(KeyCode::Function(1), true, false, false)
+        );
+        assert_eq!(
+            decode(b"\x1b[15;6~"),
+            modified(KeyCode::Function(5), true, false, true)
+        );
+        assert_eq!(
+            decode(b"\x1b[Z"),
+            modified(KeyCode::Tab, true, false, false)
+        );
+        // A modifier parameter of 1 means no modifiers
+        assert_eq!(decode(b"\x1b[1;1A"), key(KeyCode::Up));
+    }
+
+    #[test]
+    fn kitty_keys() {
+        assert_eq!(decode(b"\x1b[105;5u"), Some(KeyEvent::ctrl('i')));
+        assert_eq!(decode(b"\x1b[109;5u"), Some(KeyEvent::ctrl('m')));
+        assert_eq!(decode(b"\x1b[27u"), key(KeyCode::Esc));
+        assert_eq!(
+            decode(b"\x1b[13;2u"),
+            modified(KeyCode::Enter, true, false, false)
+        );
+        assert_eq!(
+            decode(b"\x1b[9;5u"),
+            modified(KeyCode::Tab, false, false, true)
+        );
+        assert_eq!(
+            decode(b"\x1b[127;3u"),
+            modified(KeyCode::Backspace, false, true, false)
+        );
+        assert_eq!(
+            decode(b"\x1b[120;3u"),
+            modified(KeyCode::Char('x'), false, true, false)
+        );
+        // Shift is applied to the character
+        assert_eq!(
+            decode(b"\x1b[97;6u"),
+            modified(KeyCode::Char('A'), false, false, true)
+        );
+        // Sub-parameters are ignored
+        assert_eq!(decode(b"\x1b[105;5:1u"), Some(KeyEvent::ctrl('i')));
+        assert_eq!(decode(b"\x1b[57399u"), None);
+    }
+
+    #[test]
+    fn modify_other_keys() {
+        assert_eq!(decode(b"\x1b[27;5;105~"), Some(KeyEvent::ctrl('i')));
+        assert_eq!(
+            decode(b"\x1b[27;5;13~"),
+            modified(KeyCode::Enter, false, false, true)
+        );
+        assert_eq!(
+            decode(b"\x1b[27;6;65~"),
+            modified(KeyCode::Char('A'), false, false, true)
+        );
+        assert_eq!(decode(b"\x1b[27;5~"), None);
+    }
+
+    #[test]
+    fn bracketed_paste() {
+        let paste = |text: &str| Some(Event::Paste(text.to_string()));
+        assert_eq!(decode_event(b"\x1b[200~:q!\x1b[201~"), paste(":q!"));
+        assert_eq!(
+            decode_event(b"\x1b[200~a\rb\r\nc\x1b[201~"),
+            paste("a\nb\nc")
+        );
+        assert_eq!(decode_event(b"\x1b[200~\x1b[A\x1b[201~"), paste("\x1b[A"));
+        assert_eq!(decode_event("\x1b[200~äö\x1b[201~".as_bytes()), paste("äö"));
+        assert_eq!(decode_event(b"\x1b[200~\x1b[201~"), paste(""));
+        // The end of the paste was cut off
+        assert_eq!(decode_event(b"\x1b[200~ab"), paste("ab"));
+    }
+
+    #[test]
+    fn mouse() {
+        let mouse = |kind, column, row, modifiers| {
+            Some(Event::Mouse(MouseEvent {
+                kind,
+                column,
+                row,
+                modifiers,
+            }))
+        };
+        assert_eq!(
+            decode_event(b"\x1b[<0;10;5M"),
+            mouse(MouseKind::Press(MouseButton::Left), 9, 4, Modifiers::NONE)
+        );
+        assert_eq!(
+            decode_event(b"\x1b[<0;10;5m"),
+            mouse(MouseKind::Release(MouseButton::Left), 9, 4, Modifiers::NONE)
+        );
+        assert_eq!(
+            decode_event(b"\x1b[<32;1;1M"),
+            mouse(MouseKind::Drag(MouseButton::Left), 0, 0, Modifiers::NONE)
+        );
+        assert_eq!(
+            decode_event(b"\x1b[<2;120;40M"),
+            mouse(
+                MouseKind::Press(MouseButton::Right),
+                119,
+                39,
+                Modifiers::NONE
+            )
+        );
+        assert_eq!(
+            decode_event(b"\x1b[<64;3;3M"),
+            mouse(MouseKind::ScrollUp, 2, 2, Modifiers::NONE)
+        );
+        assert_eq!(
+            decode_event(b"\x1b[<81;3;3M"),
+            mouse(MouseKind::ScrollDown, 2, 2, Modifiers::CTRL)
+        );
+        assert_eq!(decode_event(b"\x1b[<35;3;3M"), None);
+        assert_eq!(decode_event(b"\x1b[<0;3M"), None);
+    }
+
+    #[test]
+    fn unknown_and_cut_off_sequences() {
+        assert_eq!(decode(b"\x1b[?62;22c"), None);
+        assert_eq!(decode(b"\x1b[99~"), None);
+        assert_eq!(decode(b"\x1b[1;5"), None);
+        assert_eq!(decode(b"\x1b[X"), None);
+        assert_eq!(decode(b"\x1bOX"), None);
     }
 }
@@ -0,0 +1,127 @@
+use crate::grapheme::{self, graphemes};
+
+// Maps the char columns of a line to render columns, the terminal cells the line is drawn in.
+// A tab extends to the next tab stop and wide characters take up two cells.
+pub struct LineLayout {
+    // Char column, render column and width of each grapheme cluster
+    spans: Vec<(usize, usize, usize)>,
+    // Number of chars in the line
+    len: usize,
+    // Number of cells the whole line takes up
+    width: usize,
+}
+
+impl LineLayout {
+    pub fn new(line: &str, tab_width: usize) -> LineLayout {
+        let tab_width = tab_width.max(1);
+        let mut spans = vec![];
+        let mut render_column = 0;
+        let mut len = 0;
+
+        for (column, grapheme) in graphemes(line) {
+            let width = match grapheme {
+                "\t" => tab_width - render_column % tab_width,
+                _ => grapheme::width(grapheme),
+            };
+            spans.push((column, render_column, width));
+            render_column += width;
+            len = column + grapheme.chars().count();
+        }
+
+        LineLayout {
+            spans,
+            len,
+            width: render_column,
+        }
+    }
+
+    // Char column, render column and width of each grapheme cluster in the line
+    pub fn spans(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.spans.iter().copied()
+    }
+
+    // The first cell of the grapheme cluster at the column,
+    // columns after the end of the line take up one cell each
+    pub fn render_column(&self, column: usize) -> usize {
+        match self.span_at(column) {
+            Some((_, render_column, _)) => render_column,
+            None => self.width + (column - self.len),
+        }
+    }
+
+    // Number of cells of the grapheme cluster at the column
+    pub fn width_at(&self, column: usize) -> usize {
+        match self.span_at(column) {
+            Some((_, _, width)) => width,
+            None => 1,
+        }
+    }
+
+    // The char column of the grapheme cluster drawn in the cell, the opposite of render_column.
+    // Cells after the end of the line map to columns after the end.
+    pub fn column_at(&self, render_column: usize) -> usize {
+        if render_column >= self.width {
+            return self.len + (render_column - self.width);
+        }
+        let index = self
+            .spans
+            .partition_point(|(_, start, _)| *start <= render_column);
+        self.spans[index - 1].0
+    }
+
+    fn span_at(&self, column: usize) -> Option<(usize, usize, usize)> {
+        if column >= self.len {
+            return None;
+        }
+        let index = self.spans.partition_point(|(start, _, _)| *start <= column);
+        Some(self.spans[index - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_columns(line: &str, tab_width: usize) -> Vec<usize> {
+        let layout = LineLayout::new(line, tab_width);
+        (0..=line.chars().count())
+            .map(|column| layout.render_column(column))
+            .collect()
+    }
+
+    #[test]
+    fn tab_stops() {
+        assert_eq!(render_columns("\tx", 4), [0, 4, 5]);
+        assert_eq!(render_columns("ab\tx", 4), [0, 1, 2, 4, 5]);
+        assert_eq!(render_columns("abc\t\tx", 4), [0, 1, 2, 3, 4, 8, 9]);
+        assert_eq!(render_columns("abcd\tx", 4), [0, 1, 2, 3, 4, 8, 9]);
+        // A tab width of 0 is treated as 1
+        assert_eq!(render_columns("a\tx", 0), [0, 1, 2, 3]);
+
+        let layout = LineLayout::new("a\tx", 4);
+        assert_eq!(layout.width_at(1), 3);
+        assert_eq!(layout.width_at(3), 1);
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!(render_columns("a漢b", 4), [0, 1, 3, 4]);
+        // A grapheme cluster of several chars starts in one cell
+        assert_eq!(render_columns("e\u{301}x", 4), [0, 0, 1, 2]);
+        assert_eq!(LineLayout::new("a漢b", 4).width_at(1), 2);
+    }
+
+    #[test]
+    fn columns_at_render_columns() {
+        let layout = LineLayout::new("a\t漢b", 4);
+        let columns: Vec<usize> = (0..9).map(|cell| layout.column_at(cell)).collect();
+        // Every cell of a tab or a wide character maps to its column,
+        // cells after the end of the line to columns after the end
+        assert_eq!(columns, [0, 1, 1, 1, 2, 2, 3, 4, 5]);
+
+        let layout = LineLayout::new("", 4);
+        assert_eq!(layout.column_at(0), 0);
+        assert_eq!(layout.column_at(3), 3);
+        assert_eq!(layout.render_column(3), 3);
+    }
+}
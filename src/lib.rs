@@ -1,13 +1,34 @@
+mod backend;
 mod cli_argument;
+mod command;
+mod command_line;
 mod constants;
 mod content;
+mod crash;
 mod editor;
+mod event;
+mod file_type;
+mod frame;
+mod grapheme;
+mod history;
 mod input;
+mod layout;
 mod logger;
+mod options;
 mod screen;
+mod script;
+mod search;
 mod stdin_raw_mode;
+mod substitute;
 
+pub use backend::{AnsiBackend, Backend, MemoryBackend, Style};
 pub use cli_argument::CliArguments;
+pub use content::Content;
+pub use crash::install_panic_hook;
 pub use editor::Editor;
+pub use event::{Event, EventSource};
+pub use input::{KeyCode, KeyEvent, Modifiers, MouseButton, MouseEvent, MouseKind};
 pub use logger::LogLevel;
 pub use logger::Logger;
+pub use screen::{Screen, Status, View};
+pub use script::ScriptedInput;
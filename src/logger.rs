@@ -1,11 +1,14 @@
 use std::{
-    fs::File,
+    fs::{self, File},
     io::Write,
     sync::{Arc, Mutex},
 };
 
 use crate::constants::LOG_LEVEL;
 
+const LOG_DIRECTORY: &str = "logs";
+const LOG_FILE: &str = "logs/miv-log.txt";
+
 #[derive(PartialEq, PartialOrd)]
 pub enum LogLevel {
     Trace,
@@ -51,11 +54,13 @@ pub struct Logger {
 
 impl Logger {
     pub fn new() -> Logger {
+        // The directory is not part of the repository, e.g. in a fresh clone
+        fs::create_dir_all(LOG_DIRECTORY).unwrap();
         let mut logger = Logger {
             log_file: File::options()
                 .append(true)
                 .create(true)
-                .open("logs/miv-log.txt")
+                .open(LOG_FILE)
                 .unwrap(),
         };
 
@@ -85,6 +90,12 @@ impl Logger {
     }
 }
 
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Drop for Logger {
     fn drop(&mut self) {
         self.log_file.flush().unwrap();
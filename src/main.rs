@@ -1,9 +1,17 @@
 use clap::Parser;
-use miv::{CliArguments, Editor};
+use miv::{install_panic_hook, CliArguments, Editor};
+use std::process;
 
 fn main() {
     let args = CliArguments::parse();
-    let mut editor = Editor::new().unwrap();
+    install_panic_hook();
+    let mut editor = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("miv: {}", e);
+            process::exit(1);
+        }
+    };
 
     if let Some(filename) = args.file {
         editor.editor_open_with_file(filename);
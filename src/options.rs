@@ -0,0 +1,58 @@
+use crate::constants::TAB_WIDTH;
+
+// Options that can be changed at runtime with ':set'
+pub struct Options {
+    pub tab_width: usize,
+
+    // Searches ignore case, unless 'smart_case' is set and the pattern contains uppercase letters
+    pub ignore_case: bool,
+    pub smart_case: bool,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            tab_width: *TAB_WIDTH,
+            ignore_case: false,
+            smart_case: false,
+        }
+    }
+
+    // Sets an option by name, 'value' is None for boolean options written as 'option' or 'nooption'
+    pub fn set(&mut self, option: &str, value: Option<&str>) -> Result<(), String> {
+        match (option, value) {
+            ("tabstop" | "ts", Some(value)) => {
+                let tab_width = parse_number(option, value)?;
+                if tab_width == 0 {
+                    return Err("E487: Argument must be positive".to_string());
+                }
+                self.tab_width = tab_width;
+                Ok(())
+            }
+            ("tabstop" | "ts", None) => Err(format!("E518: Value required: {}", option)),
+            (_, None) => {
+                let (name, enabled) = match option.strip_prefix("no") {
+                    Some(name) => (name, false),
+                    None => (option, true),
+                };
+                *self.bool_option(name)? = enabled;
+                Ok(())
+            }
+            _ => Err(format!("E518: Unknown option: {}", option)),
+        }
+    }
+
+    fn bool_option(&mut self, name: &str) -> Result<&mut bool, String> {
+        match name {
+            "ignorecase" | "ic" => Ok(&mut self.ignore_case),
+            "smartcase" | "scs" => Ok(&mut self.smart_case),
+            _ => Err(format!("E518: Unknown option: {}", name)),
+        }
+    }
+}
+
+fn parse_number(option: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("E521: Number required after =: {}={}", option, value))
+}
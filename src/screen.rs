@@ -1,34 +1,99 @@
+use crate::backend::{AnsiBackend, Backend, Style};
+use crate::constants::{TAB_WIDTH, VERSION};
 use crate::content::Content;
-use crate::{constants::TAB_WIDTH, constants::VERSION, trace};
-use core::str;
-use std::{
-    io::{self, Error, Write},
-    usize,
-};
-
-// TODO: How can we react to window size changes?
-fn get_window_size() -> (usize, usize) {
-    term_size::dimensions_stdout().unwrap()
-    // TODO: Add a fallback for when term_size is not available
+use crate::frame::Frame;
+use crate::grapheme::{self, graphemes};
+use crate::layout::LineLayout;
+use crate::search::find_in_line;
+use crate::{trace, warn};
+use regex::Regex;
+use ropey::RopeSlice;
+use std::borrow::Cow;
+use std::io::Error;
+use std::mem;
+use unicode_width::UnicodeWidthStr;
+
+// Rows at the bottom of the screen used by the status bar and the message bar
+const STATUS_ROWS: usize = 2;
+
+// Black text on a yellow background for search matches
+const HIGHLIGHT: &str = "\x1b[30;43m";
+// Inverted colors for the status bar
+const STATUS_BAR: &str = "\x1b[7m";
+// Inverted colors for the match that is currently looked at, e.g. while confirming a substitution
+const CURRENT_MATCH: &str = "\x1b[7m";
+// Black text on a white background for the selection of visual mode
+const SELECTION: &str = "\x1b[30;47m";
+
+// The visible part of the content and the cursor on it
+#[derive(Clone, Copy)]
+pub struct View<'a> {
+    pub cursor_row: usize,
+    pub cursor_column: usize,
+    pub row_offset: usize,
+    // The first visible render column, see LineLayout
+    pub column_offset: usize,
+
+    // Matches of this regex are highlighted
+    pub highlight: Option<&'a Regex>,
+    // Row, start and end column of a match that is highlighted differently
+    pub current_match: Option<(usize, usize, usize)>,
+    // Row and column of the first and the last selected character
+    pub selection: Option<((usize, usize), (usize, usize))>,
 }
 
-fn write_flush(s: &str) {
-    io::stdout().write_all(s.as_bytes()).unwrap();
-    io::stdout().flush().unwrap();
+// Information about the editor shown in the status bar and the message bar
+pub struct Status<'a> {
+    pub filename: &'a str,
+    pub modified: bool,
+    pub file_type: &'a str,
+    pub mode: &'a str,
+
+    // The message bar text, with the cursor placed in it if a column is given
+    pub message: Option<(&'a str, Option<usize>)>,
 }
 
-pub struct Screen {
+pub struct Screen<B: Backend = AnsiBackend> {
+    backend: B,
     size: (usize, usize),
 
-    abuf: Vec<u8>,
+    // The frame that is being drawn and the one that is on the terminal
+    frame: Frame,
+    previous: Frame,
+
+    tab_width: usize,
+
+    // Whether what is on the terminal is unknown, e.g. after a resize,
+    // so the next frame clears it and draws everything
+    clear: bool,
 }
 
 impl Screen {
     pub fn new() -> Result<Screen, Error> {
-        let size = get_window_size();
-        let abuf = vec![];
+        Screen::with_backend(AnsiBackend::new())
+    }
+}
 
-        return Ok(Screen { size, abuf });
+impl<B: Backend> Screen<B> {
+    pub fn with_backend(mut backend: B) -> Result<Screen<B>, Error> {
+        let size = backend.size()?;
+
+        Ok(Screen {
+            backend,
+            size,
+            frame: Frame::new(size.0, size.1),
+            previous: Frame::new(size.0, size.1),
+            tab_width: *TAB_WIDTH,
+            clear: true,
+        })
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
     }
 
     pub fn get_height(&self) -> usize {
@@ -39,20 +104,54 @@ impl Screen {
         self.size.0
     }
 
-    fn append_abuf(&mut self, s: &str) {
-        self.abuf.extend(&s.as_bytes().to_vec());
+    // Height of the area the content is drawn in, without the status and message bar
+    pub fn get_text_height(&self) -> usize {
+        self.get_height().saturating_sub(STATUS_ROWS)
+    }
+
+    // Rereads the window size after the terminal was resized, returns whether the size changed
+    pub fn update_size(&mut self) -> bool {
+        let size = match self.backend.size() {
+            Ok(size) => size,
+            Err(e) => {
+                warn!("Keeping the old window size: {}", e);
+                return false;
+            }
+        };
+        if size == self.size {
+            return false;
+        }
+        trace!("Window resized to {}x{}", size.0, size.1);
+        self.size = size;
+        // Whatever the terminal did with the old frame while resizing is cleared
+        self.clear = true;
+        true
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width;
+    }
+
+    pub fn editor_refresh_screen(&mut self, content: &Content, view: &View, status: &Status) {
+        self.render(content, view, status);
+
+        if let Err(e) = self.backend.flush() {
+            warn!("Could not draw the screen: {}", e);
+        }
+        trace!("Screen refreshed");
     }
 
-    // TODO: I am passing way too many arguments here
+    // Draws the frame to the backend without flushing it
     // TODO: This is a mess, the cursor computation makes this very hard to read
-    pub fn editor_refresh_screen(
-        &mut self,
-        content: Content,
-        cursor_row: usize,
-        cursor_column: usize,
-        row_offset: usize,
-        column_offset: usize,
-    ) {
+    pub fn render(&mut self, content: &Content, view: &View, status: &Status) {
+        let View {
+            cursor_row,
+            cursor_column,
+            row_offset,
+            column_offset,
+            ..
+        } = *view;
+
         trace!("Refreshing screen");
         trace!(
             "Row offset: {}, Column offset: {}",
@@ -65,128 +164,303 @@ impl Screen {
             cursor_column
         );
 
-        // Hide the cursor to avoid flickering
-        self.append_abuf("\x1b[?25l");
-
-        // Clears the entire screen
-        // self.append_abuf("\x1b[2J");
-
-        // Moves the cursor to the top left corner
-        // We need to do this to start drawing from the top left corner
-        self.append_abuf("\x1b[H");
+        if self.frame.size() != self.size {
+            self.frame = Frame::new(self.size.0, self.size.1);
+            self.previous = Frame::new(self.size.0, self.size.1);
+            self.clear = true;
+        }
+        self.frame.clear();
+        self.draw_content(content, view);
+        self.draw_status_bar(content, status, cursor_row, cursor_column);
+        self.draw_message_bar(status.message.map_or("", |(text, _)| text));
 
-        // Show the window size
-        // self.append_abuf(&format!("{}, {}   ", self.get_height(), self.get_width()));
-        self.draw_content(&content, row_offset, column_offset);
+        // Hide the cursor to avoid flickering
+        self.backend.show_cursor(false);
 
-        let tabs_at_or_before_cursor =
-            self.tabs_at_or_before_cursor(&content.lines[cursor_row], cursor_column);
+        // Clears the entire screen, so the previous frame is blank
+        if self.clear {
+            self.backend.clear();
+            self.previous.clear();
+            self.clear = false;
+        }
 
-        self.append_abuf(&format!(
-            "\x1b[{};{}H",
-            (cursor_row - row_offset) + 1,
-            (cursor_column - column_offset) + 1 + (tabs_at_or_before_cursor * (*TAB_WIDTH - 1))
-        ));
+        self.frame.draw_changes(&self.previous, &mut self.backend);
+        mem::swap(&mut self.frame, &mut self.previous);
+
+        // The cursor stays hidden while it is outside of the text area, e.g. after scrolling with the mouse
+        let cursor_visible =
+            (row_offset..row_offset + self.get_text_height()).contains(&cursor_row);
+        if let Some((_, Some(message_column))) = status.message {
+            let y = self.get_height().saturating_sub(1);
+            self.backend.move_cursor(message_column, y);
+        } else if cursor_visible {
+            let cursor_x = match cursor_row < content.line_count() {
+                true => self.cursor_x(content.line(cursor_row), cursor_column),
+                false => 0,
+            };
+
+            self.backend.move_cursor(
+                cursor_x.saturating_sub(column_offset),
+                cursor_row - row_offset,
+            );
+        } else {
+            return;
+        }
 
         // Show the cursor again
-        self.append_abuf("\x1b[?25h");
-
-        write_flush(str::from_utf8(&self.abuf).unwrap());
-        trace!("Screen refreshed");
+        self.backend.show_cursor(true);
     }
 
-    fn draw_content(&mut self, content: &Content, row_offset: usize, column_offset: usize) {
-        // Extract content reference before the mutable borrows
-        let content_len = content.lines.len();
-        match content_len {
+    fn draw_content(&mut self, content: &Content, view: &View) {
+        match content.line_count() {
             0 => self.draw_welcome_message(),
             _ => {
-                self.draw_content_rows(content, row_offset, column_offset)
-                    .unwrap();
-                self.draw_filler_rows(content_len);
+                let visible_lines = self.draw_content_rows(content, view);
+                self.draw_filler_rows(visible_lines);
             }
         }
     }
 
-    // TODO: For some reason the lines are flickering when scrolling left and right
-    fn draw_content_rows(
-        &mut self,
-        content: &Content,
-        row_offset: usize,
-        column_offset: usize,
-    ) -> Result<(), Error> {
+    fn draw_content_rows(&mut self, content: &Content, view: &View) -> usize {
         trace!("Drawing content rows");
+        let (row_offset, column_offset) = (view.row_offset, view.column_offset);
 
-        let lines = content.lines.clone();
-
-        // Only iterate up to the minimum of screen height and content length
-        let visible_lines = lines.len().min(self.get_height());
+        // Only iterate up to the minimum of text area height and the remaining content length
+        let visible_lines = content
+            .line_count()
+            .saturating_sub(row_offset)
+            .min(self.get_text_height());
 
         for y in 0..visible_lines {
-            let line = &lines[y + row_offset]; // Use reference instead of clone
-
-            // TODO: Clean this up
-            // TODO: Support moving up and down with the cursor when the cursor is at the end of the line
-            let line_start = column_offset;
-            let line_end = line.len().min(self.get_width() + column_offset);
-            if line_start < line_end {
-                // TODO: This replacement should be done in the content or screen struct
-                self.append_abuf(
-                    &line[line_start..line_end]
-                        .to_string()
-                        .replace("\t", " ".repeat(*TAB_WIDTH).as_str()),
-                );
-            }
-
-            // Clears the line we are rerendering
-            self.append_abuf("\x1b[K");
-            if y < self.get_height() - 1 {
-                self.append_abuf("\r\n");
+            let row = y + row_offset;
+            let line = content.line(row);
+
+            let line = line.to_string();
+            let matches = match view.highlight {
+                Some(regex) => find_in_line(&line, regex),
+                None => vec![],
+            };
+            let current_match = match view.current_match {
+                Some((match_row, start, end)) if match_row == row => Some(start..end),
+                _ => None,
+            };
+
+            let layout = LineLayout::new(&line, self.tab_width);
+            let first_cell = column_offset;
+            let last_cell = column_offset + self.get_width();
+            for ((column, render_column, width), (_, grapheme)) in
+                layout.spans().zip(graphemes(&line))
+            {
+                if render_column < first_cell && render_column + width <= first_cell {
+                    continue;
+                }
+                if render_column >= last_cell {
+                    break;
+                }
+
+                let style: Style = if current_match.as_ref().is_some_and(|m| m.contains(&column)) {
+                    Some(CURRENT_MATCH)
+                } else if view
+                    .selection
+                    .is_some_and(|(start, end)| (start..=end).contains(&(row, column)))
+                {
+                    Some(SELECTION)
+                } else if matches
+                    .iter()
+                    .any(|(start, end)| (*start..*end).contains(&column))
+                {
+                    Some(HIGHLIGHT)
+                } else {
+                    None
+                };
+
+                // Tabs and wide characters cut off by the edge of the screen are drawn as spaces
+                let visible = render_column.max(first_cell)..(render_column + width).min(last_cell);
+                let x = visible.start - first_cell;
+                match grapheme {
+                    "\t" => self.frame.put(x, y, &" ".repeat(visible.len()), style),
+                    _ if visible.len() < width => {
+                        self.frame.put(x, y, &" ".repeat(visible.len()), style)
+                    }
+                    _ => self.frame.put(x, y, grapheme, style),
+                }
             }
         }
 
-        Ok(())
+        visible_lines
     }
 
     fn draw_filler_rows(&mut self, start_row: usize) {
-        for y in start_row..self.get_height() {
-            self.append_abuf("~");
-
-            // Clears the line we are rerendering
-            self.append_abuf("\x1b[K");
-
-            if y < self.get_height() - 1 {
-                self.append_abuf("\r\n");
-            }
+        for y in start_row..self.get_text_height() {
+            self.frame.put(0, y, "~", None);
         }
     }
 
     fn draw_welcome_message(&mut self) {
         let welcome_message = format!("Kilo editor -- version {}", VERSION);
-        self.append_abuf(&welcome_message);
-        // Clears the line we are rerendering
-        self.append_abuf("\x1b[K");
-
-        self.append_abuf("\r\n");
+        self.frame.put(0, 0, &welcome_message, None);
         self.draw_filler_rows(1);
     }
 
-    fn tabs_at_or_before_cursor(&self, line: &str, cursor_column: usize) -> usize {
-        let mut tabs = 0;
-        for i in 0..cursor_column + 1 {
-            if line.chars().nth(i).unwrap() == '\t' {
-                tabs += 1;
-            }
+    fn draw_status_bar(
+        &mut self,
+        content: &Content,
+        status: &Status,
+        cursor_row: usize,
+        cursor_column: usize,
+    ) {
+        let left = format!(
+            "{}{} - {} lines",
+            status.filename,
+            if status.modified { " [+]" } else { "" },
+            content.line_count()
+        );
+        let right = format!(
+            "{} | {} | {}:{}",
+            status.file_type,
+            status.mode,
+            cursor_row + 1,
+            cursor_column + 1
+        );
+
+        // The left side is cut off if both sides do not fit
+        let width = self.get_width();
+        let left = grapheme::truncate(&left, width.saturating_sub(right.len() + 1));
+        let padding = width.saturating_sub(UnicodeWidthStr::width(left) + right.len());
+        let bar = format!("{}{}{}", left, " ".repeat(padding), right);
+
+        self.frame
+            .put(0, self.get_text_height(), &bar, Some(STATUS_BAR));
+    }
+
+    fn draw_message_bar(&mut self, message: &str) {
+        if let Some(y) = self.get_height().checked_sub(1) {
+            self.frame.put(0, y, message, None);
+        }
+    }
+
+    // Render column of the cursor, it is drawn on the last cell of a tab like in vim
+    fn cursor_x(&self, line: RopeSlice, cursor_column: usize) -> usize {
+        let layout = LineLayout::new(&Cow::from(line), self.tab_width);
+        let render_column = layout.render_column(cursor_column);
+        match line.get_char(cursor_column) {
+            Some('\t') => render_column + layout.width_at(cursor_column) - 1,
+            _ => render_column,
         }
-        tabs
     }
 }
 
-impl Drop for Screen {
-    fn drop(&mut self) {
-        self.abuf.clear();
-        self.append_abuf("\x1b[2J");
-        self.append_abuf("\x1b[H");
-        write_flush(str::from_utf8(&self.abuf).unwrap());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    const STATUS: Status = Status {
+        filename: "test.txt",
+        modified: false,
+        file_type: "text",
+        mode: "NORMAL",
+        message: None,
+    };
+
+    fn view(row_offset: usize, column_offset: usize) -> View<'static> {
+        View {
+            cursor_row: row_offset,
+            cursor_column: 0,
+            row_offset,
+            column_offset,
+            highlight: None,
+            current_match: None,
+            selection: None,
+        }
+    }
+
+    fn screen(height: usize) -> Screen<MemoryBackend> {
+        let mut screen = Screen::with_backend(MemoryBackend::new(40, height)).unwrap();
+        screen.set_tab_width(4);
+        screen
+    }
+
+    // The rows on the backend without trailing blanks
+    fn render(screen: &mut Screen<MemoryBackend>, content: &Content, view: &View) -> Vec<String> {
+        screen.render(content, view, &STATUS);
+        let rows = screen.backend().rows();
+        rows.iter().map(|row| row.trim_end().to_string()).collect()
+    }
+
+    #[test]
+    fn row_offset() {
+        let content = Content::from_text("one\ntwo\nthree\nfour");
+        let mut screen = screen(6);
+
+        assert_eq!(
+            render(&mut screen, &content, &view(1, 0)),
+            [
+                "two",
+                "three",
+                "four",
+                "~",
+                "test.txt - 4 lines   text | NORMAL | 2:1",
+                "",
+            ]
+        );
+        assert_eq!(screen.backend().cursor(), Some((0, 0)));
+    }
+
+    #[test]
+    fn column_offset() {
+        let content = Content::from_text("a\tb\n日本語\nabc");
+        let mut screen = screen(5);
+
+        // What is left of a tab or a wide character cut off by the left edge is drawn as spaces
+        assert_eq!(
+            render(&mut screen, &content, &view(0, 1)),
+            [
+                "   b",
+                " 本語",
+                "bc",
+                "test.txt - 3 lines   text | NORMAL | 1:1",
+                "",
+            ]
+        );
+        // The cursor stays on the left edge while its column is scrolled out of view
+        assert_eq!(screen.backend().cursor(), Some((0, 0)));
+    }
+
+    #[test]
+    fn only_changes_are_drawn() {
+        let content = Content::from_text("日本語\nabc\n\ta");
+        let mut blank = screen(5);
+        let mut screen = screen(5);
+        render(&mut screen, &content, &view(0, 0));
+
+        // Drawing the next frame over the previous one ends up like drawing it on a blank screen
+        let rows = render(&mut screen, &content, &view(1, 1));
+        assert_eq!(rows, render(&mut blank, &content, &view(1, 1)));
+        assert_eq!(rows[..3], ["bc", "   a", "~"]);
+
+        // Only the cells that changed are written
+        render(&mut screen, &Content::from_text("日本語\nabc"), &view(0, 0));
+        screen.backend_mut().take_writes();
+
+        render(&mut screen, &Content::from_text("日本語\nabc"), &view(0, 0));
+        assert_eq!(screen.backend_mut().take_writes(), []);
+
+        render(&mut screen, &Content::from_text("日本語\naxc"), &view(0, 0));
+        assert_eq!(
+            screen.backend_mut().take_writes(),
+            [(1, 1, "x".to_string())]
+        );
+
+        // A wide character is written from its first cell
+        render(&mut screen, &Content::from_text("日本人\naxc"), &view(0, 0));
+        assert_eq!(
+            screen.backend_mut().take_writes(),
+            [(4, 0, "人".to_string())]
+        );
+
+        // Cells that became blank at the end of a row are cleared
+        render(&mut screen, &Content::from_text("日本人\nax"), &view(0, 0));
+        assert_eq!(screen.backend_mut().take_writes(), [(2, 1, " ".repeat(38))]);
     }
 }
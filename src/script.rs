@@ -0,0 +1,147 @@
+use crate::event::{Event, EventSource};
+use crate::input::{code_point_key, KeyCode, KeyEvent, Modifiers};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Plays back keys instead of reading the terminal, e.g. to drive the editor in tests.
+// Keys are written like vim's key notation: "ihello<Esc>:w<CR>", "<C-q>" is Ctrl-q,
+// "<A-x>" is Alt-x and "<lt>" a literal '<'.
+pub struct ScriptedInput {
+    events: VecDeque<Event>,
+}
+
+impl ScriptedInput {
+    pub fn new(keys: &str) -> ScriptedInput {
+        ScriptedInput {
+            events: parse_keys(keys).into_iter().map(Event::Key).collect(),
+        }
+    }
+}
+
+impl EventSource for ScriptedInput {
+    // The script never waits, the editor stops once all keys were played back
+    fn read_event(&mut self, _: Option<Duration>) -> Option<Event> {
+        self.events.pop_front()
+    }
+}
+
+fn parse_keys(keys: &str) -> Vec<KeyEvent> {
+    let mut events = vec![];
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        // A '<' that does not start a known key is taken literally
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|s| s.split_once('>'))
+            .and_then(|(name, after)| Some((parse_key(name)?, after)));
+        match special {
+            Some((key, after)) => {
+                events.push(key);
+                rest = after;
+            }
+            None => {
+                events.push(KeyEvent::new(KeyCode::Char(c)));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    events
+}
+
+// Parses what is inside the angle brackets, e.g. "Esc" or "C-Left"
+fn parse_key(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = Modifiers::NONE;
+    let mut name = name;
+    loop {
+        match name.get(..2) {
+            Some("C-" | "c-") => modifiers.ctrl = true,
+            Some("A-" | "a-" | "M-" | "m-") => modifiers.alt = true,
+            Some("S-" | "s-") => modifiers.shift = true,
+            _ => break,
+        }
+        name = &name[2..];
+    }
+
+    // A modified character is decoded like one reported by the terminal
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return match modifiers {
+            Modifiers::NONE => None,
+            _ => code_point_key(c as u32, modifiers),
+        };
+    }
+
+    let code = match name.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "bs" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        name => match name.strip_prefix('f')?.parse() {
+            Ok(n @ 1..=12) => KeyCode::Function(n),
+            _ => return None,
+        },
+    };
+    Some(KeyEvent::with_modifiers(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c))
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_keys("j$é"), [key('j'), key('$'), key('é')]);
+        assert_eq!(
+            parse_keys("i<Esc><CR><bs><PageDown><F5>"),
+            [
+                key('i'),
+                KeyEvent::new(KeyCode::Esc),
+                KeyEvent::new(KeyCode::Enter),
+                KeyEvent::new(KeyCode::Backspace),
+                KeyEvent::new(KeyCode::PageDown),
+                KeyEvent::new(KeyCode::Function(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(parse_keys("<C-q>"), [KeyEvent::ctrl('q')]);
+        assert_eq!(
+            parse_keys("<A-S-x><C-Left>"),
+            [
+                KeyEvent::with_modifiers(
+                    KeyCode::Char('X'),
+                    Modifiers {
+                        alt: true,
+                        ..Modifiers::NONE
+                    }
+                ),
+                KeyEvent::with_modifiers(KeyCode::Left, Modifiers::CTRL),
+            ]
+        );
+    }
+
+    #[test]
+    fn literal_angle_brackets() {
+        assert_eq!(parse_keys("<lt>"), [key('<')]);
+        assert_eq!(parse_keys("<x>"), [key('<'), key('x'), key('>')]);
+        assert_eq!(parse_keys("a<b"), [key('a'), key('<'), key('b')]);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::content::Content;
+use regex::{Regex, RegexBuilder};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn reverse(self) -> Direction {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+}
+
+pub struct Match {
+    pub row: usize,
+    pub column: usize,
+
+    // Whether the search went past the end (or start) of the content to find the match
+    pub wrapped: bool,
+}
+
+// Compiles a search pattern, with 'smart_case' an uppercase letter in the pattern overrides 'ignore_case'
+pub fn build_regex(pattern: &str, ignore_case: bool, smart_case: bool) -> Result<Regex, String> {
+    let has_uppercase = pattern.chars().any(char::is_uppercase);
+
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case && !(smart_case && has_uppercase))
+        .build()
+        .map_err(|_| format!("E383: Invalid search string: {}", pattern))
+}
+
+// Converts a byte index into the line to a char index
+pub fn char_index(line: &str, byte_index: usize) -> usize {
+    line[..byte_index].chars().count()
+}
+
+// Converts a char index into the line to a byte index
+pub fn byte_index(line: &str, char_index: usize) -> usize {
+    line.char_indices()
+        .nth(char_index)
+        .map_or(line.len(), |(index, _)| index)
+}
+
+// Returns the char ranges of all non overlapping matches of the regex in the line
+pub fn find_in_line(line: &str, regex: &Regex) -> Vec<(usize, usize)> {
+    let mut column = 0;
+    let mut last_index = 0;
+    regex
+        .find_iter(line)
+        .map(|found| {
+            column += char_index(&line[last_index..], found.start() - last_index);
+            last_index = found.start();
+            (column, column + found.as_str().chars().count())
+        })
+        .collect()
+}
+
+// Finds the next match of the regex starting at the given position, excluding the position itself.
+// Wraps around the end (or start) of the content.
+pub fn find(
+    content: &Content,
+    regex: &Regex,
+    (row, column): (usize, usize),
+    direction: Direction,
+) -> Option<Match> {
+    let line_count = content.line_count();
+    if line_count == 0 {
+        return None;
+    }
+
+    // The start row is searched twice, the second time for matches on the other side of the cursor
+    for i in 0..=line_count {
+        let current_row = match direction {
+            Direction::Forward => (row + i) % line_count,
+            Direction::Backward => (row + line_count - i % line_count) % line_count,
+        };
+        let matches = find_in_line(&content.line(current_row).to_string(), regex);
+
+        let found = match (direction, i) {
+            (Direction::Forward, 0) => matches.into_iter().find(|(start, _)| *start > column),
+            (Direction::Forward, _) => matches.into_iter().next(),
+            (Direction::Backward, 0) => {
+                matches.into_iter().rev().find(|(start, _)| *start < column)
+            }
+            (Direction::Backward, _) => matches.into_iter().last(),
+        };
+
+        if let Some((start, _)) = found {
+            return Some(Match {
+                row: current_row,
+                column: start,
+                wrapped: match direction {
+                    Direction::Forward => row + i >= line_count,
+                    Direction::Backward => i > row,
+                },
+            });
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The row, column and whether the search wrapped of the next match
+    fn find_from(
+        text: &str,
+        pattern: &str,
+        position: (usize, usize),
+        direction: Direction,
+    ) -> Option<(usize, usize, bool)> {
+        let regex = build_regex(pattern, false, false).unwrap();
+        let found = find(&Content::from_text(text), &regex, position, direction)?;
+        Some((found.row, found.column, found.wrapped))
+    }
+
+    #[test]
+    fn forward() {
+        let text = "ab ab\nxx\nab";
+        assert_eq!(
+            find_from(text, "ab", (0, 0), Direction::Forward),
+            Some((0, 3, false))
+        );
+        assert_eq!(
+            find_from(text, "ab", (0, 3), Direction::Forward),
+            Some((2, 0, false))
+        );
+        assert_eq!(
+            find_from(text, "ab", (1, 1), Direction::Forward),
+            Some((2, 0, false))
+        );
+        // Wraps around the end to the start of the content
+        assert_eq!(
+            find_from(text, "ab", (2, 0), Direction::Forward),
+            Some((0, 0, true))
+        );
+        assert_eq!(
+            find_from(text, "x", (1, 1), Direction::Forward),
+            Some((1, 0, true))
+        );
+    }
+
+    #[test]
+    fn backward() {
+        let text = "ab ab\nxx\nab";
+        assert_eq!(
+            find_from(text, "ab", (2, 0), Direction::Backward),
+            Some((0, 3, false))
+        );
+        assert_eq!(
+            find_from(text, "ab", (0, 3), Direction::Backward),
+            Some((0, 0, false))
+        );
+        // Wraps around the start to the end of the content
+        assert_eq!(
+            find_from(text, "ab", (0, 0), Direction::Backward),
+            Some((2, 0, true))
+        );
+        assert_eq!(
+            find_from(text, "x", (1, 0), Direction::Backward),
+            Some((1, 1, true))
+        );
+    }
+
+    #[test]
+    fn only_match_and_no_match() {
+        // The only match is found again after going around the whole content
+        assert_eq!(
+            find_from("a\nb", "b", (1, 0), Direction::Forward),
+            Some((1, 0, true))
+        );
+        assert_eq!(
+            find_from("a\nb", "b", (1, 0), Direction::Backward),
+            Some((1, 0, true))
+        );
+        assert_eq!(find_from("a\nb", "c", (0, 0), Direction::Forward), None);
+        assert_eq!(find_from("", "c", (0, 0), Direction::Backward), None);
+    }
+
+    #[test]
+    fn regex_patterns() {
+        assert_eq!(
+            find_from("a1 b22\nc333", r"\d{2,}", (0, 0), Direction::Forward),
+            Some((0, 4, false))
+        );
+        assert_eq!(
+            find_from("ab\nab", "^b|b$", (0, 1), Direction::Forward),
+            Some((1, 1, false))
+        );
+        assert_eq!(
+            build_regex("a(", false, false).err(),
+            Some("E383: Invalid search string: a(".to_string())
+        );
+    }
+
+    #[test]
+    fn case() {
+        let matches = |pattern, ignore_case, smart_case, text| {
+            build_regex(pattern, ignore_case, smart_case)
+                .unwrap()
+                .is_match(text)
+        };
+        assert!(!matches("abc", false, false, "ABC"));
+        assert!(matches("abc", true, false, "ABC"));
+        assert!(matches("Abc", true, false, "aBC"));
+        // With smartcase an uppercase letter makes the search case sensitive
+        assert!(matches("abc", true, true, "ABC"));
+        assert!(!matches("Abc", true, true, "aBC"));
+        assert!(matches("Abc", true, true, "Abc"));
+        // Smartcase alone does not ignore case
+        assert!(!matches("abc", false, true, "ABC"));
+    }
+
+    #[test]
+    fn char_columns() {
+        let regex = Regex::new("é+").unwrap();
+        assert_eq!(find_in_line("é aéé é", &regex), [(0, 1), (3, 5), (6, 7)]);
+        assert_eq!(
+            find_from("日本 本", "本", (0, 1), Direction::Forward),
+            Some((0, 3, false))
+        );
+
+        assert_eq!(char_index("aé日b", 6), 3);
+        assert_eq!(byte_index("aé日b", 3), 6);
+        assert_eq!(byte_index("aé", 5), 3);
+    }
+}
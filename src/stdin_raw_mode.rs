@@ -1,16 +1,19 @@
-use crate::constants::BLOCKING_READ;
-use std::io::{self, Error};
+use std::io::{self, Error, Write};
 use std::os::fd::AsRawFd;
+use std::sync::Mutex;
 use termios::*;
 
-pub struct StdinRawMode {
-    orig_termios: Termios,
-}
+// The terminal settings from before raw mode, kept here so the panic hook can restore them too
+static ORIGINAL_TERMIOS: Mutex<Option<Termios>> = Mutex::new(None);
+
+// Raw mode is enabled while this exists
+pub struct StdinRawMode;
 
 impl StdinRawMode {
     pub fn new() -> Result<StdinRawMode, Error> {
         let orig_termios = Termios::from_fd(io::stdin().as_raw_fd()).unwrap();
-        let stdin = StdinRawMode { orig_termios };
+        *ORIGINAL_TERMIOS.lock().unwrap_or_else(|e| e.into_inner()) = Some(orig_termios);
+        let stdin = StdinRawMode;
         stdin.enable_raw_mode();
 
         Ok(stdin)
@@ -24,24 +27,53 @@ impl StdinRawMode {
         raw.c_cflag |= CS8;
         raw.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
 
-        if *BLOCKING_READ {
-            // Blocking read
-            // downside: pressing esc will not be read until a key is pressed as it could be part of an escape sequence
-            raw.c_cc[VMIN] = 1;
-            raw.c_cc[VTIME] = 0;
-        } else {
-            // Non-blocking read
-            // downside: we will consistently read 0 bytes until a key is pressed which could lead to consistent rerendering
-            raw.c_cc[VMIN] = 0;
-            raw.c_cc[VTIME] = 1;
-        }
+        // Reads block until a byte arrives, Input only reads once poll says a byte is there
+        raw.c_cc[VMIN] = 1;
+        raw.c_cc[VTIME] = 0;
 
         tcsetattr(io::stdin().as_raw_fd(), TCSAFLUSH, &raw).unwrap();
+
+        set_terminal_modes(true);
     }
 }
 
 impl Drop for StdinRawMode {
     fn drop(&mut self) {
-        tcsetattr(io::stdin().as_raw_fd(), TCSAFLUSH, &self.orig_termios).unwrap();
+        restore_terminal_mode();
     }
 }
+
+// Leaves raw mode and disables the terminal modes, only the first call does anything.
+// Errors are ignored as this also runs while panicking.
+pub fn restore_terminal_mode() {
+    let orig_termios = ORIGINAL_TERMIOS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    if let Some(orig_termios) = orig_termios {
+        set_terminal_modes(false);
+        let _ = tcsetattr(io::stdin().as_raw_fd(), TCSAFLUSH, &orig_termios);
+    }
+}
+
+// Private modes of the terminal that are enabled while in raw mode:
+// 2004 is bracketed paste, the terminal wraps pasted text in 'ESC [ 200 ~' and 'ESC [ 201 ~',
+// 1000 and 1002 report mouse clicks, drags and the wheel, 1006 sends those reports as 'ESC [ <'
+const TERMINAL_MODES: [u16; 4] = [2004, 1000, 1002, 1006];
+
+fn set_terminal_modes(enabled: bool) {
+    let sequence: String = match enabled {
+        true => TERMINAL_MODES
+            .iter()
+            .map(|mode| format!("\x1b[?{}h", mode))
+            .collect(),
+        false => TERMINAL_MODES
+            .iter()
+            .rev()
+            .map(|mode| format!("\x1b[?{}l", mode))
+            .collect(),
+    };
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(sequence.as_bytes());
+    let _ = stdout.flush();
+}
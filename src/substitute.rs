@@ -0,0 +1,253 @@
+use crate::content::Content;
+use crate::search::{byte_index, char_index};
+use regex::Regex;
+
+// Converts a vim style replacement, where '&' and '\0' to '\9' refer to the match and its groups
+// and '\r' (or '\n') inserts a line break, to the syntax used by the regex crate
+pub fn convert_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => converted.push_str(&format!("${{{}}}", digit)),
+                Some('r' | 'n') => converted.push('\n'),
+                Some('t') => converted.push('\t'),
+                Some('$') => converted.push_str("$$"),
+                Some(other) => converted.push(other),
+                None => converted.push('\\'),
+            },
+            _ => converted.push(c),
+        }
+    }
+
+    converted
+}
+
+// A match found by a substitution, the columns are char indices into the row
+#[derive(Clone, Copy)]
+pub struct SubstituteMatch {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Walks through the matches of a ':s' command one by one,
+// so each of them can either be replaced or skipped
+pub struct Substitution {
+    regex: Regex,
+    // Replacement in the syntax of the regex crate
+    replacement: String,
+    // Replace all matches in a line instead of only the first one
+    global: bool,
+
+    // Position to continue searching from
+    row: usize,
+    column: usize,
+    // Whether the position is right after a match, where an empty match is not allowed
+    after_match: bool,
+    // The last row of the range, moves when replacements add or remove line breaks
+    last_row: usize,
+
+    pub replaced: usize,
+    pub replaced_rows: usize,
+    // The row of the last replacement, the cursor is moved there when the substitution ends
+    pub last_replaced_row: Option<usize>,
+}
+
+impl Substitution {
+    pub fn new(
+        regex: Regex,
+        replacement: &str,
+        global: bool,
+        first_row: usize,
+        last_row: usize,
+    ) -> Substitution {
+        Substitution {
+            regex,
+            replacement: convert_replacement(replacement),
+            global,
+            row: first_row,
+            column: 0,
+            after_match: false,
+            last_row,
+            replaced: 0,
+            replaced_rows: 0,
+            last_replaced_row: None,
+        }
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    pub fn next_match(&mut self, content: &Content) -> Option<SubstituteMatch> {
+        while self.row <= self.last_row && self.row < content.line_count() {
+            let line = content.line(self.row).to_string();
+
+            if self.column <= line.chars().count() {
+                let start = byte_index(&line, self.column);
+                match self.regex.find_at(&line, start) {
+                    // Like in vim, ':s/a*/-/g' turns "baa" into "-b-" and not "-b--"
+                    Some(found)
+                        if self.after_match && found.is_empty() && found.start() == start =>
+                    {
+                        self.after_match = false;
+                        self.column += 1;
+                        continue;
+                    }
+                    Some(found) => {
+                        return Some(SubstituteMatch {
+                            row: self.row,
+                            start: char_index(&line, found.start()),
+                            end: char_index(&line, found.end()),
+                        })
+                    }
+                    None => {}
+                }
+            }
+
+            self.next_row();
+        }
+        None
+    }
+
+    pub fn replace(&mut self, content: &mut Content, found: SubstituteMatch) {
+        let line = content.line(found.row).to_string();
+        let (start, end) = (byte_index(&line, found.start), byte_index(&line, found.end));
+
+        let mut replacement = String::new();
+        if let Some(captures) = self.regex.captures_at(&line, start) {
+            captures.expand(&self.replacement, &mut replacement);
+        }
+        content.replace(found.row, found.start, found.end, &replacement);
+
+        if self.last_replaced_row != Some(found.row) {
+            self.replaced_rows += 1;
+        }
+        self.replaced += 1;
+
+        // Continue after the inserted text, which can span multiple lines
+        let line_breaks = replacement.matches('\n').count();
+        self.row += line_breaks;
+        self.last_row += line_breaks;
+        self.last_replaced_row = Some(self.row);
+        self.column = match replacement.rfind('\n') {
+            Some(index) => replacement[index + 1..].chars().count(),
+            None => found.start + replacement.chars().count(),
+        };
+        self.advance(start == end);
+    }
+
+    pub fn skip(&mut self, found: SubstituteMatch) {
+        self.column = found.end;
+        self.advance(found.start == found.end);
+    }
+
+    fn advance(&mut self, empty_match: bool) {
+        if !self.global {
+            self.next_row();
+        } else if empty_match {
+            // Step over a character so an empty match is not found again
+            self.column += 1;
+        } else {
+            self.after_match = true;
+        }
+    }
+
+    fn next_row(&mut self) {
+        self.row += 1;
+        self.column = 0;
+        self.after_match = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replaces every match in all lines and returns the text and the number of replacements
+    fn substitute(text: &str, pattern: &str, replacement: &str, global: bool) -> (String, usize) {
+        let mut content = Content::from_text(text);
+        let last_row = content.last_row();
+        let regex = Regex::new(pattern).unwrap();
+        let mut substitution = Substitution::new(regex, replacement, global, 0, last_row);
+        while let Some(found) = substitution.next_match(&content) {
+            substitution.replace(&mut content, found);
+        }
+        (content.text().to_string(), substitution.replaced)
+    }
+
+    #[test]
+    fn replacements() {
+        assert_eq!(convert_replacement("abc"), "abc");
+        assert_eq!(convert_replacement("<&>"), "<${0}>");
+        assert_eq!(convert_replacement(r"\2\1\0"), "${2}${1}${0}");
+        assert_eq!(convert_replacement(r"a\rb\nc\td"), "a\nb\nc\td");
+        assert_eq!(convert_replacement(r"\&\\\/"), r"&\/");
+        // '$' is literal in vim but starts a group in the regex crate
+        assert_eq!(convert_replacement(r"$1\$"), "$$1$$");
+        assert_eq!(convert_replacement("a\\"), "a\\");
+    }
+
+    #[test]
+    fn first_or_all_matches() {
+        assert_eq!(
+            substitute("aa\naa", "a", "b", false),
+            ("ba\nba".to_string(), 2)
+        );
+        assert_eq!(
+            substitute("aa\naa", "a", "b", true),
+            ("bb\nbb".to_string(), 4)
+        );
+        assert_eq!(
+            substitute("ab", "(a)(b)", r"\2\1&", false),
+            ("baab".to_string(), 1)
+        );
+        assert_eq!(substitute("ab", "x", "y", true), ("ab".to_string(), 0));
+    }
+
+    #[test]
+    fn empty_matches() {
+        assert_eq!(substitute("ab", "", "-", false), ("-ab".to_string(), 1));
+        assert_eq!(substitute("ab", "", "-", true), ("-a-b-".to_string(), 3));
+        assert_eq!(
+            substitute("ab\n\nc", "x*", "-", true),
+            ("-a-b-\n-\n-c-".to_string(), 6)
+        );
+        // The replacement is not searched again and there is no empty match right after a match
+        assert_eq!(substitute("aa", "a*", "a", true), ("a".to_string(), 1));
+        assert_eq!(substitute("baa", "a*", "-", true), ("-b-".to_string(), 2));
+    }
+
+    #[test]
+    fn multi_line_replacements() {
+        assert_eq!(
+            substitute("a,b,c", ",", r"\r", true),
+            ("a\nb\nc".to_string(), 2)
+        );
+        assert_eq!(
+            substitute("a,b\nc,d", ",", r"\r", false),
+            ("a\nb\nc\nd".to_string(), 2)
+        );
+        // Matches after the inserted line break are still found in the same line
+        assert_eq!(
+            substitute("ab\nab", "b", r"\rb", true),
+            ("a\nb\na\nb".to_string(), 2)
+        );
+
+        // The range grows with the inserted lines, so the line after it is left alone
+        let mut content = Content::from_text("a a\na a\na a");
+        let regex = Regex::new(" ").unwrap();
+        let mut substitution = Substitution::new(regex, r"\r", true, 0, 1);
+        while let Some(found) = substitution.next_match(&content) {
+            substitution.replace(&mut content, found);
+        }
+        assert_eq!(content.text().to_string(), "a\na\na\na\na a");
+        assert_eq!(substitution.last_replaced_row, Some(3));
+        assert_eq!(substitution.replaced_rows, 2);
+    }
+}